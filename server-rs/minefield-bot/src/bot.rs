@@ -6,42 +6,53 @@ use rand_distr::{Distribution, Normal};
 use websocket::ClientBuilder;
 
 use minefield_core::bot::Bot;
-use minefield_core::tiles::Tile;
-use minefield_game::protocol::{MoveType, Msg};
+use minefield_core::rules::Ruleset;
+use minefield_core::strategy;
+use minefield_game::protocol::{MoveType, Msg, PROTOCOL_VERSION};
 
 use crate::comm;
 
 type Client =
     websocket::client::sync::Client<Box<dyn websocket::sync::stream::NetworkStream + Send>>;
 
-pub fn run_bot(url: &str, nick: &str) -> Result<(), Error> {
-    let (client, bot, you) = connect(url, nick)?;
+pub fn run_bot(url: &str, nick: &str, strategy: &str) -> Result<(), Error> {
+    let (client, bot, you) = connect(url, nick, strategy)?;
     play(client, bot, you)
 }
 
-pub fn spawn_bots(url: &str, nick: &str) -> Result<(), Error> {
+pub fn spawn_bots(url: &str, nick: &str, strategy: &str) -> Result<(), Error> {
     loop {
-        let (client, bot, you) = connect(url, nick)?;
+        let (client, bot, you) = connect(url, nick, strategy)?;
         thread::spawn(move || {
             play(client, bot, you).unwrap();
         });
     }
 }
 
-pub fn connect(url: &str, nick: &str) -> Result<(Client, Bot, usize), Error> {
+pub fn connect(url: &str, nick: &str, strategy: &str) -> Result<(Client, Bot, usize), Error> {
     let mut builder = ClientBuilder::new(url)?;
     let mut client = builder.connect(None)?;
 
+    comm::send_msg(
+        &mut client,
+        &Msg::Hello {
+            protocol_version: PROTOCOL_VERSION,
+            client: format!("minefield-bot {}", env!("CARGO_PKG_VERSION")),
+            capabilities: vec![],
+        },
+    )?;
     comm::send_msg(
         &mut client,
         &Msg::NewGame {
             nick: nick.to_owned(),
+            rules: None,
+            private: None,
         },
     )?;
 
     loop {
         match comm::recv_msg(&mut client)? {
-            Msg::Room { .. } | Msg::Games { .. } => (),
+            Msg::Welcome { .. } | Msg::Room { .. } | Msg::Games { .. } => (),
 
             Msg::PhaseOne {
                 tiles,
@@ -49,8 +60,18 @@ pub fn connect(url: &str, nick: &str) -> Result<(Client, Bot, usize), Error> {
                 you,
                 east,
             } => {
-                let player_wind = if you == east { Tile::X1 } else { Tile::X3 };
-                let bot = Bot::new(&tiles, dora_ind, player_wind);
+                // we created the game with the default rules
+                let rules = Ruleset::default();
+                let player_wind = rules.player_wind(you == east);
+                let strategy = strategy::by_name(strategy).unwrap();
+                let bot = Bot::with_strategy(
+                    &tiles,
+                    dora_ind,
+                    player_wind,
+                    &rules,
+                    strategy,
+                    rand::random(),
+                );
                 return Ok((client, bot, you));
             }
             msg => {
@@ -15,6 +15,8 @@ mod comm;
 
 use clap::{App, Arg};
 
+use minefield_core::strategy::{DEFAULT_STRATEGY, STRATEGY_NAMES};
+
 fn main() {
     env_logger::builder()
         .filter_level(log::LevelFilter::Info)
@@ -27,6 +29,12 @@ fn main() {
                 .takes_value(true),
         )
         .arg(Arg::with_name("nick").long("nick").takes_value(true))
+        .arg(
+            Arg::with_name("strategy")
+                .long("strategy")
+                .takes_value(true)
+                .possible_values(&STRATEGY_NAMES),
+        )
         .arg(
             Arg::with_name("spawn")
                 .long("spawn")
@@ -39,10 +47,11 @@ fn main() {
         .unwrap_or("ws://localhost:8080/ws");
 
     let nick = matches.value_of("nick").unwrap_or("RustBot");
+    let strategy = matches.value_of("strategy").unwrap_or(DEFAULT_STRATEGY);
 
     if matches.is_present("spawn") {
-        bot::spawn_bots(server_url, nick).unwrap();
+        bot::spawn_bots(server_url, nick, strategy).unwrap();
     } else {
-        bot::run_bot(server_url, nick).unwrap();
+        bot::run_bot(server_url, nick, strategy).unwrap();
     }
 }
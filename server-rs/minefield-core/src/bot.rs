@@ -1,10 +1,16 @@
 use std::collections::HashSet;
 
-use log::{info, warn};
+use log::warn;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
 
 use crate::backtrack::{Backtrack, BacktrackStrategy};
+use crate::inference::DangerMap;
+use crate::rules::Ruleset;
 use crate::score::Score;
 use crate::search::{find_all_waits, search};
+use crate::seed::Seeder;
+use crate::strategy::{self, Strategy};
 use crate::tiles::{Tile, TileSet};
 use crate::yaku::Yaku;
 
@@ -75,73 +81,70 @@ fn find_all_tenpai(tiles: &[Tile]) -> Vec<Vec<Tile>> {
     result
 }
 
-pub struct Bot {
-    initial_tiles: Vec<Tile>,
-    tile_set: TileSet,
-    safe_tiles: HashSet<Tile>,
-    waits: HashSet<Tile>,
-    dora_ind: Tile,
-    dora: Tile,
-    player_wind: Tile,
+// What the bot knows about the game, shared by all strategies.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BotState {
+    pub initial_tiles: Vec<Tile>,
+    // tiles left to choose from
+    pub tile_set: TileSet,
+    // opponent's discards
+    pub safe_tiles: HashSet<Tile>,
+    pub waits: HashSet<Tile>,
+    // all discards so far, in order
+    #[serde(default)]
+    pub discards: Vec<Tile>,
+    #[serde(default)]
+    pub opponent_discards: Vec<Tile>,
+    pub dora_ind: Tile,
+    pub dora: Tile,
+    pub player_wind: Tile,
+    #[serde(default)]
+    pub rules: Ruleset,
+    // No time limits, so that the moves depend only on the seed
+    #[serde(default)]
+    pub reproducible: bool,
 }
 
-impl Bot {
+impl BotState {
     pub fn new(initial_tiles: &[Tile], dora_ind: Tile, player_wind: Tile) -> Self {
-        Bot {
+        BotState {
             initial_tiles: initial_tiles.to_vec(),
             tile_set: TileSet::from_tiles(initial_tiles),
             safe_tiles: HashSet::new(),
             waits: HashSet::new(),
+            discards: vec![],
+            opponent_discards: vec![],
             dora_ind,
             dora: dora_ind.next_wrap(),
             player_wind,
+            rules: Ruleset::default(),
+            reproducible: false,
         }
     }
 
-    pub fn choose_hand(&mut self) -> Vec<Tile> {
-        let hand: Vec<Tile> = match self.find_best_tenpai() {
-            Some(hand) => {
-                info!("found a tenpai");
-                hand
-            }
-            None => {
-                warn!("no tenpai!");
-                self.initial_tiles[..13].to_vec()
+    // All possible tenpai hands, sorted and without repetitions.
+    pub fn all_tenpai(&self) -> Vec<Vec<Tile>> {
+        let mut seen = HashSet::new();
+        let mut result = vec![];
+        for mut tiles in find_all_tenpai(&self.initial_tiles).into_iter() {
+            tiles.sort();
+            if seen.insert(tiles.clone()) {
+                result.push(tiles);
             }
-        };
-        self.tile_set.add_all(&hand, -1);
-        for wait in find_all_waits(&hand) {
-            self.waits.insert(wait);
         }
-
-        hand
-    }
-
-    pub fn choose_discard(&mut self) -> Tile {
-        let discard = self.find_discard();
-        self.tile_set.add(discard, -1);
-        discard
+        result
     }
 
-    pub fn opponent_discard(&mut self, tile: Tile) {
-        self.safe_tiles.insert(tile);
-    }
-
-    fn find_best_tenpai(&self) -> Option<Vec<Tile>> {
+    // The tenpai with best (positive) value, according to `eval`.
+    pub fn find_best_tenpai<F>(&self, eval: F) -> Option<Vec<Tile>>
+    where
+        F: Fn(&Self, &[Tile]) -> Option<f64>,
+    {
         let mut best_tenpai = None;
         let mut best_value = 0.0;
 
-        let mut seen = HashSet::new();
-
-        let tenpais = find_all_tenpai(&self.initial_tiles);
-        for mut tiles in tenpais.into_iter() {
-            tiles.sort();
-            if seen.contains(&tiles) {
-                continue;
-            }
-            seen.insert(tiles.clone());
-
-            if let Some(value) = self.eval_tenpai(&tiles) {
+        for tiles in self.all_tenpai().into_iter() {
+            if let Some(value) = eval(self, &tiles) {
                 if value > best_value {
                     best_tenpai = Some(tiles);
                     best_value = value;
@@ -152,13 +155,29 @@ impl Bot {
         best_tenpai
     }
 
-    fn eval_tenpai(&self, tiles: &[Tile]) -> Option<f64> {
-        let mut tiles = tiles.to_vec();
-        let mut all = vec![];
-        let mut good = vec![];
-        let mut all_count = 0;
-        let mut good_count = 0;
+    // Tiles that could still be in the opponent's hand.
+    pub fn unseen(&self) -> TileSet {
+        let mut unseen = TileSet::new();
+        for tile in Tile::all() {
+            unseen.add(tile, 4);
+        }
+        unseen.add_all(&self.initial_tiles, -1);
+        unseen.add_all(&self.opponent_discards, -1);
+        unseen.add(self.dora_ind, -1);
+        unseen
+    }
+
+    // Used when there is no tenpai to choose.
+    pub fn fallback_hand(&self) -> Vec<Tile> {
+        warn!("no tenpai!");
+        self.initial_tiles[..13].to_vec()
+    }
 
+    // Waits for the hand, as (wait, number of copies possibly left, best
+    // limit reached with riichi and dora).
+    pub fn wait_scores(&self, tiles: &[Tile]) -> Vec<(Tile, isize, usize)> {
+        let mut tiles = tiles.to_vec();
+        let mut result = vec![];
         for wait in Tile::all() {
             tiles.push(wait);
             let hands = search(&tiles, wait);
@@ -167,7 +186,7 @@ impl Bot {
                 .map(|hand| {
                     Score::from_hand(hand, self.player_wind, &[Yaku::Riichi])
                         .with_dora(self.dora)
-                        .limit()
+                        .limit(&self.rules)
                 })
                 .max()
                 .unwrap_or(0);
@@ -177,15 +196,27 @@ impl Bot {
                 count -= 1;
             }
             if count > 0 {
-                all.push((count, max_score));
-                all_count += count;
-                if max_score > 0 {
-                    good.push((count, max_score));
-                    good_count += count;
-                }
+                result.push((wait, count, max_score));
             }
             tiles.pop();
         }
+        result
+    }
+
+    pub fn eval_tenpai(&self, tiles: &[Tile]) -> Option<f64> {
+        let mut all = vec![];
+        let mut good = vec![];
+        let mut all_count = 0;
+        let mut good_count = 0;
+
+        for (_, count, max_score) in self.wait_scores(tiles) {
+            all.push((count, max_score));
+            all_count += count;
+            if max_score > 0 {
+                good.push((count, max_score));
+                good_count += count;
+            }
+        }
 
         if good_count == 0 {
             return None;
@@ -205,32 +236,173 @@ impl Bot {
         Some(prob_some * expected_win * (good_count as f64) / (all_count as f64))
     }
 
-    fn find_discard(&self) -> Tile {
-        use rand::seq::SliceRandom;
+    // Chance of getting through all the discards without dealing in, judging
+    // only by what we know before the opponent's first discard. We get to
+    // keep the most dangerous of the tiles left.
+    pub fn pool_safety(&self, tiles: &[Tile], danger: &DangerMap) -> f64 {
+        let mut pool = TileSet::from_tiles(&self.initial_tiles);
+        pool.add_all(tiles, -1);
+        let mut pool_danger = vec![];
+        for tile in pool.distinct() {
+            for _ in 0..pool.get(tile) {
+                pool_danger.push(danger.get(tile));
+            }
+        }
+        pool_danger.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        pool_danger
+            .iter()
+            .take(self.rules.discards)
+            .map(|d| 1.0 - d.min(1.0))
+            .product()
+    }
+
+    // Expected win, discounted by the risk of the tiles left to discard.
+    // The danger map is the same for all hands, so it is passed in.
+    pub fn eval_tenpai_safe(&self, tiles: &[Tile], danger: &DangerMap) -> Option<f64> {
+        self.eval_tenpai(tiles)
+            .map(|value| value * self.pool_safety(tiles, danger))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Bot {
+    #[serde(flatten)]
+    state: BotState,
+    #[serde(with = "strategy::by_name", default = "strategy::default_strategy")]
+    strategy: Box<dyn Strategy>,
+    #[serde(default = "Seeder::from_entropy")]
+    seeder: Seeder,
+}
 
-        // safe tile, if any
-        let remaining_set = self.tile_set.as_hash_set();
-        let mut safe = remaining_set.intersection(&self.safe_tiles);
-        if let Some(tile) = safe.next() {
-            info!("found safe tile");
-            return *tile;
+impl Bot {
+    pub fn new(initial_tiles: &[Tile], dora_ind: Tile, player_wind: Tile) -> Self {
+        Self::with_strategy(
+            initial_tiles,
+            dora_ind,
+            player_wind,
+            &Ruleset::default(),
+            strategy::default_strategy(),
+            rand::random(),
+        )
+    }
+
+    pub fn with_strategy(
+        initial_tiles: &[Tile],
+        dora_ind: Tile,
+        player_wind: Tile,
+        rules: &Ruleset,
+        strategy: Box<dyn Strategy>,
+        seed: u64,
+    ) -> Self {
+        let mut state = BotState::new(initial_tiles, dora_ind, player_wind);
+        state.rules = rules.clone();
+        Bot {
+            state,
+            strategy,
+            seeder: Seeder::new(seed),
         }
+    }
+
+    pub fn set_reproducible(&mut self, reproducible: bool) {
+        self.state.reproducible = reproducible;
+    }
+
+    // Starting seed, for the game record.
+    pub fn seed(&self) -> u64 {
+        self.seeder.seed()
+    }
+
+    pub fn strategy_name(&self) -> &'static str {
+        self.strategy.name()
+    }
+
+    pub fn choose_hand(&mut self) -> Vec<Tile> {
+        let bot_move = self.think(MoveKind::Hand).run();
+        self.apply(&bot_move);
+        match bot_move {
+            BotMove::Hand(hand) => hand,
+            BotMove::Discard(_) => unreachable!(),
+        }
+    }
+
+    pub fn choose_discard(&mut self) -> Tile {
+        let bot_move = self.think(MoveKind::Discard).run();
+        self.apply(&bot_move);
+        match bot_move {
+            BotMove::Discard(tile) => tile,
+            BotMove::Hand(_) => unreachable!(),
+        }
+    }
+
+    // Copies what's needed to choose the move, so that the search can run
+    // elsewhere (see `Thought`). The strategy is a fresh one: same as after
+    // a restart, anything it keeps outside of `BotState` is lost.
+    pub fn think(&mut self, kind: MoveKind) -> Thought {
+        Thought {
+            kind,
+            state: self.state.clone(),
+            strategy: strategy::by_name(self.strategy.name()).unwrap(),
+            rng: self.seeder.rng(),
+        }
+    }
 
-        // most common (but not in our waits)
-        let mut most_common: Vec<Tile> = self.tile_set.distinct().collect();
-        most_common.shuffle(&mut rand::thread_rng());
-        most_common.sort_by_key(|t| self.tile_set.get(*t));
-        for tile in most_common.iter() {
-            if !self.waits.contains(tile) {
-                info!("found common tile");
-                return *tile;
+    // Records a move chosen by `Thought::run`. The opponent's discards that
+    // came in the meantime don't matter, they don't change our tiles.
+    pub fn apply(&mut self, bot_move: &BotMove) {
+        match bot_move {
+            BotMove::Hand(hand) => {
+                self.state.tile_set.add_all(hand, -1);
+                for wait in find_all_waits(hand) {
+                    self.state.waits.insert(wait);
+                }
+            }
+            BotMove::Discard(tile) => {
+                self.state.tile_set.add(*tile, -1);
+                self.state.discards.push(*tile);
             }
         }
+    }
+
+    pub fn opponent_discard(&mut self, tile: Tile) {
+        self.state.safe_tiles.insert(tile);
+        self.state.opponent_discards.push(tile);
+        self.strategy.opponent_discard(&self.state, tile);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveKind {
+    Hand,
+    Discard,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BotMove {
+    Hand(Vec<Tile>),
+    Discard(Tile),
+}
+
+// A move being chosen, independent of the bot. The search can take a while,
+// so this can be sent to another thread instead of holding up the game.
+pub struct Thought {
+    kind: MoveKind,
+    state: BotState,
+    strategy: Box<dyn Strategy>,
+    rng: StdRng,
+}
+
+impl Thought {
+    pub fn kind(&self) -> MoveKind {
+        self.kind
+    }
 
-        // furiten ahoy
-        assert!(!most_common.is_empty());
-        warn!("furiten!");
-        most_common[0]
+    pub fn run(mut self) -> BotMove {
+        match self.kind {
+            MoveKind::Hand => BotMove::Hand(self.strategy.choose_hand(&self.state, &mut self.rng)),
+            MoveKind::Discard => {
+                BotMove::Discard(self.strategy.choose_discard(&self.state, &mut self.rng))
+            }
+        }
     }
 }
 
@@ -238,17 +410,18 @@ impl Bot {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::inference::Observation;
     use Tile::*;
 
     fn assert_bot(tiles: &[Tile], dora_ind: Tile, player_wind: Tile) {
-        let bot = Bot::new(tiles, dora_ind, player_wind);
-        let best_tenpai = bot.find_best_tenpai();
+        let state = BotState::new(tiles, dora_ind, player_wind);
+        let best_tenpai = state.find_best_tenpai(BotState::eval_tenpai);
         assert!(best_tenpai.is_some());
     }
 
     fn assert_bot_fails(tiles: &[Tile], dora_ind: Tile, player_wind: Tile) {
-        let bot = Bot::new(tiles, dora_ind, player_wind);
-        let best_tenpai = bot.find_best_tenpai();
+        let state = BotState::new(tiles, dora_ind, player_wind);
+        let best_tenpai = state.find_best_tenpai(BotState::eval_tenpai);
         assert!(best_tenpai.is_none());
     }
 
@@ -304,4 +477,49 @@ mod test {
             X3,
         );
     }
+
+    #[test]
+    fn test_pool_safety() {
+        let tiles = [
+            M2, M3, M5, M6, M7, M7, M8, M9, M9, P1, P3, P5, P6, P6, P7, P8, S1, S2, S2, S3, S4, S6,
+            S7, S7, S8, X1, X2, X2, X4, X4, X4, X5, X6, X7,
+        ];
+        let mut state = BotState::new(&tiles, X4, X3);
+        let danger = Observation::from_bot(&state).danger_map();
+        let honors_left = state.pool_safety(&tiles[..13], &danger);
+        let numbers_left = state.pool_safety(&tiles[21..], &danger);
+        assert!(0.0 < numbers_left && numbers_left < honors_left && honors_left <= 1.0);
+
+        // fewer discards, fewer chances to deal in
+        state.rules.discards = 10;
+        assert!(state.pool_safety(&tiles[21..], &danger) > numbers_left);
+    }
+
+    #[test]
+    fn test_thought() {
+        let tiles = [
+            M2, M3, M5, M6, M7, M7, M8, M9, M9, P1, P3, P5, P6, P6, P7, P8, S1, S2, S2, S3, S4, S6,
+            S7, S7, S8, X1, X2, X2, X4, X4, X4, X5, X6, X7,
+        ];
+        let new_bot = || {
+            let strategy = strategy::by_name("random").unwrap();
+            Bot::with_strategy(&tiles, X4, X3, &Ruleset::default(), strategy, 1)
+        };
+        let mut bot = new_bot();
+        let hand = bot.choose_hand();
+        let discard = bot.choose_discard();
+
+        // same moves when thinking separately, with the opponent discarding
+        // in the meantime
+        let mut bot = new_bot();
+        let thought = bot.think(MoveKind::Hand);
+        bot.opponent_discard(P5);
+        let bot_move = thought.run();
+        assert_eq!(bot_move, BotMove::Hand(hand));
+        bot.apply(&bot_move);
+        let bot_move = bot.think(MoveKind::Discard).run();
+        assert_eq!(bot_move, BotMove::Discard(discard));
+        bot.apply(&bot_move);
+        assert_eq!(bot.state.discards, vec![discard]);
+    }
 }
@@ -1,44 +1,132 @@
+use serde::{Deserialize, Serialize};
+
 use crate::hand::Group;
 use crate::hand::Hand;
 use crate::tiles::Tile;
 
-pub fn fu(hand: &Hand, player_wind: Tile) -> usize {
-    match hand {
-        Hand::Normal(pair, groups, wait, wait_group) => {
-            let is_open_wait = wait_group.map_or(false, |g| g.is_open_wait(*wait));
-            let is_pon_wait = wait_group.map_or(false, |g| g.is_pon());
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WaitType {
+    Ryanmen,
+    Kanchan,
+    Penchan,
+    Tanki,
+    Shanpon,
+}
+
+impl WaitType {
+    pub fn fu(self) -> usize {
+        match self {
+            WaitType::Ryanmen | WaitType::Shanpon => 0,
+            WaitType::Kanchan | WaitType::Penchan | WaitType::Tanki => 2,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PonFu {
+    pub tile: Tile,
+    // completed by the winning tile
+    pub open: bool,
+    // terminal or honor
+    pub terminal: bool,
+    pub fu: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FuBreakdown {
+    pub base: usize,
+    pub closed_ron: usize,
+    pub wait_type: Option<WaitType>,
+    pub wait: usize,
+    pub pair: usize,
+    pub pons: Vec<PonFu>,
+    // rounded up
+    pub total: usize,
+}
+
+impl FuBreakdown {
+    fn flat(base: usize, closed_ron: usize) -> Self {
+        FuBreakdown {
+            base,
+            closed_ron,
+            wait_type: None,
+            wait: 0,
+            pair: 0,
+            pons: vec![],
+            total: base + closed_ron,
+        }
+    }
+
+    pub fn sum(&self) -> usize {
+        self.base
+            + self.closed_ron
+            + self.wait
+            + self.pair
+            + self.pons.iter().map(|p| p.fu).sum::<usize>()
+    }
+}
 
-            // pinfu
-            if groups.iter().all(|g| g.is_chi()) && !pair.is_yakuhai(player_wind) && is_open_wait {
-                return 30;
+fn wait_type(wait: Tile, wait_group: Option<Group>) -> WaitType {
+    match wait_group {
+        None => WaitType::Tanki,
+        Some(Group::Pon(_)) => WaitType::Shanpon,
+        Some(group @ Group::Chi(t)) => {
+            if group.is_open_wait(wait) {
+                WaitType::Ryanmen
+            } else if wait == t.next() {
+                WaitType::Kanchan
+            } else {
+                WaitType::Penchan
             }
+        }
+    }
+}
 
-            let mut fu = 30;
+pub fn fu(hand: &Hand, player_wind: Tile) -> FuBreakdown {
+    match hand {
+        Hand::Normal(pair, groups, wait, wait_group) => {
+            // Always a closed hand won by ron
+            let mut result = FuBreakdown::flat(20, 10);
+
+            let wait_type = wait_type(*wait, *wait_group);
+            result.wait_type = Some(wait_type);
+            result.wait = wait_type.fu();
 
             if pair.is_yakuhai(player_wind) {
-                fu += 2;
-            }
-            if !is_pon_wait && !is_open_wait {
-                fu += 2;
+                result.pair = 2;
             }
+
             for group in groups.iter() {
                 if let Group::Pon(tile) = group {
+                    let open = *wait_group == Some(*group);
+                    let terminal = tile.is_yaochu();
                     let mut p = 2;
-                    if tile.is_yaochu() {
+                    if terminal {
                         p *= 2;
                     }
-                    // Closed pon
-                    if *wait_group != Some(*group) {
+                    if !open {
                         p *= 2;
                     }
-                    fu += p;
+                    result.pons.push(PonFu {
+                        tile: *tile,
+                        open,
+                        terminal,
+                        fu: p,
+                    });
                 }
             }
 
-            (fu + 9) / 10 * 10
+            // Round up to 10 (pinfu comes out at 30)
+            result.total = (result.sum() + 9) / 10 * 10;
+            result
+        }
+        Hand::Pairs(_, _) => {
+            let mut result = FuBreakdown::flat(25, 0);
+            result.wait_type = Some(WaitType::Tanki);
+            result
         }
-        Hand::Pairs(_, _) => 25,
-        Hand::Kokushi(_, _) => 30,
+        Hand::Kokushi(_, _) => FuBreakdown::flat(20, 10),
     }
 }
 
@@ -55,7 +143,7 @@ mod tests {
         println!("tiles: {:?}", tiles);
         for hand in search(tiles, wait).iter() {
             println!("hand: {:?}", hand);
-            result.push(fu(hand, player_wind));
+            result.push(fu(hand, player_wind).total);
         }
         assert_eq!(result, expected);
     }
@@ -186,4 +274,54 @@ mod tests {
             &[50, 40],
         );
     }
+
+    #[test]
+    fn test_fu_breakdown() {
+        // 20 + 10 + 2 (kanchan) + 2 (dragon pair) + 8 + 4 = 46
+        let hands = search(
+            &[M1, M1, M1, M4, M5, M6, P3, P3, P3, S1, S3, X5, X5, S2],
+            S2,
+        );
+        assert_eq!(hands.len(), 1);
+        assert_eq!(
+            fu(&hands[0], X1),
+            FuBreakdown {
+                base: 20,
+                closed_ron: 10,
+                wait_type: Some(WaitType::Kanchan),
+                wait: 2,
+                pair: 2,
+                pons: vec![
+                    PonFu {
+                        tile: M1,
+                        open: false,
+                        terminal: true,
+                        fu: 8
+                    },
+                    PonFu {
+                        tile: P3,
+                        open: false,
+                        terminal: false,
+                        fu: 4
+                    },
+                ],
+                total: 50,
+            }
+        );
+    }
+
+    #[test]
+    fn test_wait_type() {
+        let wait_types = |tiles: &[Tile; 14], wait: Tile| -> Vec<Option<WaitType>> {
+            search(tiles, wait)
+                .iter()
+                .map(|hand| fu(hand, X1).wait_type)
+                .collect()
+        };
+        let tiles = [M1, M2, M3, P1, P2, P3, S1, S2, S3, S4, S5, S6, S7, S7];
+        assert_eq!(wait_types(&tiles, S6), vec![Some(WaitType::Ryanmen)]);
+        assert_eq!(wait_types(&tiles, S5), vec![Some(WaitType::Kanchan)]);
+        assert_eq!(wait_types(&tiles, M3), vec![Some(WaitType::Penchan)]);
+        assert_eq!(wait_types(&tiles, S7), vec![Some(WaitType::Tanki)]);
+    }
 }
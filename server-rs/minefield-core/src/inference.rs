@@ -0,0 +1,657 @@
+// Reading the opponent's waits from what we can see.
+//
+// We go through all the tenpai hands the opponent could hold, made of the
+// tiles we cannot see, and weigh each by the number of ways to hold it. A
+// hand is dropped if any of its waits would leave the opponent furiten.
+// Genbutsu, suji, kabe and honor counting all fall out of that:
+// - a tile the opponent discarded, or didn't win on, is never a wait,
+// - so no hand waits on its suji with a ryanmen (or a sanmenchan) either,
+// - a hand needing a tile we can see all four of cannot be held,
+// - an honor can only be waited on with the copies still unseen.
+//
+// There are far too many hands to go through one by one, so we split them by
+// suit. Whether a hand is tenpai, and on what, only depends on whether the
+// part of it in each suit is complete (groups, and maybe a pair) or one tile
+// short of complete. We list these parts once, and count the ways to combine
+// them. Seven pairs and kokushi are counted on their own. A seven pairs hand
+// that is also a normal tenpai is counted with the normal hands only (its
+// single tile is always one of the normal waits).
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::sync::OnceLock;
+
+use crate::bot::BotState;
+use crate::tiles::{Tile, TileSet, NUM_TILES};
+
+const HAND_SIZE: usize = 13;
+
+// Everything visible to us.
+#[derive(Debug, Clone)]
+pub struct Observation {
+    pub opponent_discards: Vec<Tile>,
+    // Tiles the opponent could have won on, but didn't
+    pub passed: Vec<Tile>,
+    pub dora_ind: Tile,
+    pub own_tiles: Vec<Tile>,
+}
+
+impl Observation {
+    // What the bot knows: all our discards are tiles the opponent passed on.
+    pub fn from_bot(state: &BotState) -> Self {
+        Observation {
+            opponent_discards: state.opponent_discards.clone(),
+            passed: state.discards.clone(),
+            dora_ind: state.dora_ind,
+            own_tiles: state.initial_tiles.clone(),
+        }
+    }
+
+    // Tiles that are never a wait, unless the opponent is furiten (and so
+    // harmless anyway).
+    pub fn genbutsu(&self) -> HashSet<Tile> {
+        self.opponent_discards
+            .iter()
+            .chain(self.passed.iter())
+            .cloned()
+            .collect()
+    }
+
+    // Copies of each tile we cannot see.
+    pub fn unseen(&self) -> TileSet {
+        let mut unseen = TileSet::new();
+        for tile in Tile::all() {
+            let mut count = 4;
+            count -= self.own_tiles.iter().filter(|t| **t == tile).count() as isize;
+            count -= self
+                .opponent_discards
+                .iter()
+                .filter(|t| **t == tile)
+                .count() as isize;
+            if tile == self.dora_ind {
+                count -= 1;
+            }
+            unseen.add(tile, count.max(0));
+        }
+        unseen
+    }
+
+    pub fn danger_map(&self) -> DangerMap {
+        let unseen = self.unseen();
+        let genbutsu = self.genbutsu();
+        let suits: Vec<SuitTally> = SUITS
+            .iter()
+            .map(|(first, runs)| SuitTally::new(*first, *runs, &unseen, &genbutsu))
+            .collect();
+
+        let normal = normal_hands(&suits, |s| &s.complete, |s| &s.pair, |s| &s.waiting);
+        let mut pairs = Tally::new();
+        for sizes in splits() {
+            pairs.add(&combine_suits(&sizes, |s| &suits[s].pairs));
+        }
+        let normal_pairs = normal_hands(
+            &suits,
+            |s| &s.pairs_complete,
+            |s| &s.pairs_complete,
+            |s| &s.pairs_waiting,
+        );
+        let kokushi = kokushi(&unseen, &genbutsu);
+
+        // Seven pairs hands have one wait each.
+        let mut total = normal.weight + kokushi.weight;
+        let mut danger = [0.0; NUM_TILES];
+        for (i, d) in danger.iter_mut().enumerate() {
+            let pairs = (pairs.waits[i] - normal_pairs.waits[i]).max(0.0);
+            total += pairs;
+            *d = normal.waits[i] + pairs + kokushi.waits[i];
+        }
+        if total > 0.0 {
+            for d in danger.iter_mut() {
+                *d /= total;
+            }
+        }
+        DangerMap(danger)
+    }
+}
+
+// First tile of each suit, and whether it has runs.
+const SUITS: [(usize, bool); 4] = [(0, true), (9, true), (18, true), (27, false)];
+
+// Part of a hand within one suit, as counts by number.
+type Counts = [u8; 9];
+
+#[derive(Debug, Clone, Default)]
+struct Part {
+    counts: Counts,
+    size: usize,
+    // Groups, and a pair if the size calls for one
+    complete: bool,
+    // Numbers that make it complete, as a bit mask
+    waits: u16,
+    // For seven pairs: only pairs, and at most one single tile
+    pairs: bool,
+    single: Option<usize>,
+}
+
+// The parts that can make up a tenpai hand, for a suit with `len` numbers.
+fn parts(len: usize, runs: bool) -> Vec<Part> {
+    let mut groups = vec![];
+    for n in 0..len {
+        let mut pon = [0; 9];
+        pon[n] = 3;
+        groups.push(pon);
+        if runs && n + 2 < len {
+            let mut chi = [0; 9];
+            chi[n..n + 3].copy_from_slice(&[1, 1, 1]);
+            groups.push(chi);
+        }
+    }
+    let mut complete = BTreeSet::new();
+    add_groups(&groups, 0, 4, [0; 9], &mut complete);
+    for n in 0..len {
+        let mut pair = [0; 9];
+        pair[n] = 2;
+        add_groups(&groups, 0, 4, pair, &mut complete);
+    }
+
+    let mut parts = BTreeMap::new();
+    for counts in complete.iter() {
+        part(&mut parts, *counts).complete = true;
+        for n in 0..len {
+            if counts[n] > 0 {
+                let mut short = *counts;
+                short[n] -= 1;
+                part(&mut parts, short).waits |= 1 << n;
+            }
+        }
+    }
+    add_pairs(len, 0, [0; 9], None, &mut parts);
+    parts.into_values().collect()
+}
+
+fn part(parts: &mut BTreeMap<Counts, Part>, counts: Counts) -> &mut Part {
+    parts.entry(counts).or_insert_with(|| Part {
+        counts,
+        size: counts.iter().map(|c| *c as usize).sum(),
+        ..Part::default()
+    })
+}
+
+// Adds the counts, and everything made of them and up to `left` more groups.
+// Groups are added in order, so that each set of them comes up once.
+fn add_groups(
+    groups: &[Counts],
+    from: usize,
+    left: usize,
+    counts: Counts,
+    result: &mut BTreeSet<Counts>,
+) {
+    result.insert(counts);
+    if left == 0 {
+        return;
+    }
+    for (i, group) in groups.iter().enumerate().skip(from) {
+        let mut next = counts;
+        for (c, g) in next.iter_mut().zip(group.iter()) {
+            *c += g;
+        }
+        if next.iter().all(|c| *c <= 4) {
+            add_groups(groups, i, left - 1, next, result);
+        }
+    }
+}
+
+fn add_pairs(
+    len: usize,
+    n: usize,
+    counts: Counts,
+    single: Option<usize>,
+    parts: &mut BTreeMap<Counts, Part>,
+) {
+    if n == len {
+        let part = part(parts, counts);
+        part.pairs = true;
+        part.single = single;
+        return;
+    }
+    for count in 0..=2 {
+        if count == 1 && single.is_some() {
+            continue;
+        }
+        let mut next = counts;
+        next[n] = count;
+        let single = if count == 1 { Some(n) } else { single };
+        add_pairs(len, n + 1, next, single, parts);
+    }
+}
+
+// Same for every game, so only listed once.
+fn suit_parts(runs: bool) -> &'static [Part] {
+    static NUMBERS: OnceLock<Vec<Part>> = OnceLock::new();
+    static HONORS: OnceLock<Vec<Part>> = OnceLock::new();
+    if runs {
+        NUMBERS.get_or_init(|| parts(9, true))
+    } else {
+        HONORS.get_or_init(|| parts(7, false))
+    }
+}
+
+// Weight of some hands (or parts of hands), and of the ones waiting on each
+// tile.
+#[derive(Debug, Clone)]
+struct Tally {
+    weight: f64,
+    waits: [f64; NUM_TILES],
+}
+
+impl Tally {
+    fn new() -> Self {
+        Tally {
+            weight: 0.0,
+            waits: [0.0; NUM_TILES],
+        }
+    }
+
+    fn add(&mut self, other: &Tally) {
+        self.weight += other.weight;
+        for (w, o) in self.waits.iter_mut().zip(other.waits.iter()) {
+            *w += o;
+        }
+    }
+
+    // Hands made of one part from each slot. The waits come from any one of
+    // the slots, with the rest of the hand counted by weight.
+    fn combine(slots: &[&Tally]) -> Tally {
+        let mut result = Tally::new();
+        result.weight = slots.iter().map(|s| s.weight).product();
+        for (i, slot) in slots.iter().enumerate() {
+            let others: f64 = slots
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, s)| s.weight)
+                .product();
+            for (w, s) in result.waits.iter_mut().zip(slot.waits.iter()) {
+                *w += s * others;
+            }
+        }
+        result
+    }
+}
+
+// Parts of a hand in one suit, by size.
+struct SuitTally {
+    // Normal hands: all complete parts (waits not counted), complete parts
+    // with a pair, and parts one tile short of complete. The last two only
+    // if not furiten.
+    complete: Vec<Tally>,
+    pair: Vec<Tally>,
+    waiting: Vec<Tally>,
+    // Seven pairs: weight of only pairs, waits on the single tile if not
+    // furiten. Also the ones that are complete, or waiting, as above, to tell
+    // which hands are normal tenpai too.
+    pairs: Vec<Tally>,
+    pairs_complete: Vec<Tally>,
+    pairs_waiting: Vec<Tally>,
+}
+
+impl SuitTally {
+    fn new(first: usize, runs: bool, unseen: &TileSet, genbutsu: &HashSet<Tile>) -> Self {
+        let tiles: Vec<Tile> = Tile::all().skip(first).take(9).collect();
+        let mut furiten = 0;
+        for (n, tile) in tiles.iter().enumerate() {
+            if genbutsu.contains(tile) {
+                furiten |= 1 << n;
+            }
+        }
+
+        let empty = vec![Tally::new(); HAND_SIZE + 1];
+        let mut result = SuitTally {
+            complete: empty.clone(),
+            pair: empty.clone(),
+            waiting: empty.clone(),
+            pairs: empty.clone(),
+            pairs_complete: empty.clone(),
+            pairs_waiting: empty,
+        };
+        for part in suit_parts(runs).iter() {
+            if part.size > HAND_SIZE {
+                continue;
+            }
+            let weight: f64 = part
+                .counts
+                .iter()
+                .zip(tiles.iter())
+                .map(|(c, t)| choose(unseen.get(*t), *c as isize))
+                .product();
+            if weight == 0.0 {
+                continue;
+            }
+            let k = part.size;
+
+            let mut tally = Tally::new();
+            tally.weight = weight;
+            for n in 0..tiles.len() {
+                if part.waits & (1 << n) != 0 {
+                    tally.waits[first + n] = weight;
+                }
+            }
+            let not_furiten = part.waits & furiten == 0;
+            if part.complete {
+                result.complete[k].weight += weight;
+                if k % 3 == 2 && not_furiten {
+                    result.pair[k].add(&tally);
+                }
+            } else if part.waits != 0 && not_furiten {
+                result.waiting[k].add(&tally);
+            }
+
+            if part.pairs {
+                let mut tally = Tally::new();
+                match part.single {
+                    None => tally.weight = weight,
+                    Some(n) if furiten & (1 << n) == 0 => tally.waits[first + n] = weight,
+                    Some(_) => (),
+                }
+                result.pairs[k].add(&tally);
+                if part.complete {
+                    result.pairs_complete[k].add(&tally);
+                } else if part.waits != 0 {
+                    result.pairs_waiting[k].add(&tally);
+                }
+            }
+        }
+        result
+    }
+}
+
+// Ways to split a hand between the suits, by size.
+fn splits() -> Vec<[usize; 4]> {
+    let mut result = vec![];
+    for a in 0..=HAND_SIZE {
+        for b in 0..=HAND_SIZE - a {
+            for c in 0..=HAND_SIZE - a - b {
+                result.push([a, b, c, HAND_SIZE - a - b - c]);
+            }
+        }
+    }
+    result
+}
+
+fn combine_suits<'a>(sizes: &[usize; 4], tallies: impl Fn(usize) -> &'a [Tally]) -> Tally {
+    let slots: Vec<&Tally> = (0..4).map(|s| &tallies(s)[sizes[s]]).collect();
+    Tally::combine(&slots)
+}
+
+// Normal tenpai hands. Either one part is waiting and the others are
+// complete, or two parts are complete with a pair and each can become groups
+// (a shanpon, or the like, across suits). A waiting part of size 3n+1 needs a
+// pair to be complete, so the others have none; one of size 3n+2 needs a
+// pair somewhere else.
+fn normal_hands(
+    suits: &[SuitTally],
+    complete: fn(&SuitTally) -> &[Tally],
+    pair: fn(&SuitTally) -> &[Tally],
+    waiting: fn(&SuitTally) -> &[Tally],
+) -> Tally {
+    let mut result = Tally::new();
+    for sizes in splits() {
+        let with_pair: Vec<usize> = (0..4).filter(|s| sizes[*s] % 3 == 2).collect();
+        let with_single: Vec<usize> = (0..4).filter(|s| sizes[*s] % 3 == 1).collect();
+        let waiting_in = |w: usize| {
+            combine_suits(&sizes, |s| {
+                if s == w {
+                    waiting(&suits[s])
+                } else {
+                    complete(&suits[s])
+                }
+            })
+        };
+        match (with_single.as_slice(), with_pair.as_slice()) {
+            ([w], []) => result.add(&waiting_in(*w)),
+            ([], [a, b]) => {
+                result.add(&waiting_in(*a));
+                result.add(&waiting_in(*b));
+                result.add(&combine_suits(&sizes, |s| {
+                    if s == *a || s == *b {
+                        pair(&suits[s])
+                    } else {
+                        complete(&suits[s])
+                    }
+                }));
+            }
+            _ => (),
+        }
+    }
+    result
+}
+
+// One of each terminal and honor, or one of them doubled and waiting on
+// another.
+fn kokushi(unseen: &TileSet, genbutsu: &HashSet<Tile>) -> Tally {
+    let tiles: Vec<Tile> = Tile::all().filter(|t| t.is_yaochu()).collect();
+    let ways = |skip: &[Tile]| -> f64 {
+        tiles
+            .iter()
+            .filter(|t| !skip.contains(t))
+            .map(|t| choose(unseen.get(*t), 1))
+            .product()
+    };
+
+    let mut result = Tally::new();
+    if !tiles.iter().any(|t| genbutsu.contains(t)) {
+        let weight = ways(&[]);
+        result.weight += weight;
+        for tile in tiles.iter() {
+            result.waits[*tile as usize] += weight;
+        }
+    }
+    for wait in tiles.iter().filter(|t| !genbutsu.contains(t)) {
+        for double in tiles.iter().filter(|t| *t != wait) {
+            let weight = choose(unseen.get(*double), 2) * ways(&[*wait, *double]);
+            result.weight += weight;
+            result.waits[*wait as usize] += weight;
+        }
+    }
+    result
+}
+
+// Number of ways to pick k out of n tiles.
+fn choose(n: isize, k: isize) -> f64 {
+    let mut result = 1.0;
+    for i in 0..k {
+        result *= (n - i).max(0) as f64 / (i + 1) as f64;
+    }
+    result
+}
+
+// Share of the opponent's possible hands that win on each tile: 0 for a tile
+// that cannot be a wait, more the more likely it is.
+#[derive(Debug, Clone)]
+pub struct DangerMap([f64; NUM_TILES]);
+
+impl DangerMap {
+    pub fn get(&self, tile: Tile) -> f64 {
+        self.0[tile as usize]
+    }
+
+    // All tiles, safest first.
+    pub fn ranked(&self) -> Vec<Tile> {
+        let mut tiles: Vec<Tile> = Tile::all().collect();
+        tiles.sort_by(|a, b| self.get(*a).partial_cmp(&self.get(*b)).unwrap());
+        tiles
+    }
+}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::search::find_all_waits;
+    use Tile::*;
+
+    fn observation(opponent_discards: &[Tile], own_tiles: &[Tile]) -> Observation {
+        Observation {
+            opponent_discards: opponent_discards.to_vec(),
+            passed: vec![],
+            dora_ind: X7,
+            own_tiles: own_tiles.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_genbutsu() {
+        let mut obs = observation(&[M5], &[]);
+        obs.passed = vec![P3];
+        let map = obs.danger_map();
+        assert_eq!(map.get(M5), 0.0);
+        assert_eq!(map.get(P3), 0.0);
+        assert!(map.get(M4) > 0.0);
+        assert!(map.ranked()[..2].contains(&M5));
+    }
+
+    #[test]
+    fn test_suji() {
+        let map = observation(&[S6], &[]).danger_map();
+        // no 4-5 ryanmen
+        assert!(map.get(S3) < map.get(P3));
+        assert!(map.get(S3) < map.get(S2));
+        // no 7-8 ryanmen
+        assert!(map.get(S9) < map.get(P9));
+    }
+
+    #[test]
+    fn test_kabe() {
+        // all four P8 visible: no 7-8 ryanmen
+        let obs = observation(&[], &[P8, P8, P8, P8]);
+        let map = obs.danger_map();
+        assert!(map.get(P9) < map.get(M9));
+        assert!(map.get(P6) < map.get(M6));
+    }
+
+    #[test]
+    fn test_honors() {
+        // all copies visible: only kokushi waits on it
+        let mut obs = observation(&[], &[X5, X5, X5]);
+        obs.dora_ind = X5;
+        let map = obs.danger_map();
+        assert!(map.get(X5) > 0.0);
+        assert!(map.get(X5) < map.get(X1) / 100.0);
+        // two copies left: fewer ways to wait on it
+        let map = observation(&[], &[X6, X6]).danger_map();
+        assert!(map.get(X6) > 0.0);
+        assert!(map.get(X6) < map.get(X1));
+    }
+
+    // Only the given tiles unseen (and the dora indicator X7 seen).
+    fn with_unseen(unseen: &[Tile], passed: &[Tile]) -> Observation {
+        let mut own = TileSet::new();
+        for tile in Tile::all() {
+            own.add(tile, 4);
+        }
+        own.add(X7, -1);
+        own.add_all(unseen, -1);
+        let mut own_tiles = vec![];
+        for tile in own.distinct() {
+            for _ in 0..own.get(tile) {
+                own_tiles.push(tile);
+            }
+        }
+        Observation {
+            opponent_discards: vec![],
+            passed: passed.to_vec(),
+            dora_ind: X7,
+            own_tiles,
+        }
+    }
+
+    // Goes through the hands one by one, the way `danger_map` would with all
+    // the time in the world.
+    fn brute_force(obs: &Observation) -> [f64; NUM_TILES] {
+        fn go(
+            tiles: &[Tile],
+            unseen: &TileSet,
+            genbutsu: &HashSet<Tile>,
+            hand: &mut Vec<Tile>,
+            weight: f64,
+            result: &mut Tally,
+        ) {
+            if hand.len() == HAND_SIZE {
+                let waits = find_all_waits(hand);
+                if !waits.is_empty() && !waits.iter().any(|w| genbutsu.contains(w)) {
+                    result.weight += weight;
+                    for wait in waits {
+                        result.waits[wait as usize] += weight;
+                    }
+                }
+                return;
+            }
+            if let Some((tile, rest)) = tiles.split_first() {
+                for count in 0..=unseen.get(*tile) {
+                    if hand.len() + count as usize > HAND_SIZE {
+                        break;
+                    }
+                    let before = hand.len();
+                    hand.extend((0..count).map(|_| *tile));
+                    let weight = weight * choose(unseen.get(*tile), count);
+                    go(rest, unseen, genbutsu, hand, weight, result);
+                    hand.truncate(before);
+                }
+            }
+        }
+
+        let unseen = obs.unseen();
+        let tiles: Vec<Tile> = unseen.distinct().collect();
+        let mut result = Tally::new();
+        go(
+            &tiles,
+            &unseen,
+            &obs.genbutsu(),
+            &mut vec![],
+            1.0,
+            &mut result,
+        );
+        let mut danger = result.waits;
+        for d in danger.iter_mut() {
+            *d /= result.weight;
+        }
+        danger
+    }
+
+    fn assert_brute_force(obs: &Observation) {
+        let map = obs.danger_map();
+        let expected = brute_force(obs);
+        for tile in Tile::all() {
+            let (a, b) = (map.get(tile), expected[tile as usize]);
+            assert!((a - b).abs() < 1e-9, "{:?}: {} != {}", tile, a, b);
+        }
+    }
+
+    #[test]
+    fn test_normal_hands() {
+        let obs = with_unseen(
+            &[
+                M1, M1, M2, M3, M4, M4, M5, M6, M7, M8, M8, P1, P2, P3, X1, X1, X2,
+            ],
+            &[M9],
+        );
+        assert_brute_force(&obs);
+    }
+
+    #[test]
+    fn test_seven_pairs() {
+        let obs = with_unseen(
+            &[
+                M1, M1, M2, M2, M3, M3, M4, M4, M5, M5, M6, M6, P7, P7, X1, X1, S5,
+            ],
+            &[X1],
+        );
+        assert_brute_force(&obs);
+    }
+
+    #[test]
+    fn test_kokushi() {
+        let yaochu = [
+            M1, M9, P1, P9, S1, S9, X1, X2, X3, X4, X5, X6, X7, M1, X3, M2,
+        ];
+        assert_brute_force(&with_unseen(&yaochu, &[]));
+        assert_brute_force(&with_unseen(&yaochu, &[M9]));
+    }
+}
@@ -8,8 +8,16 @@ pub mod backtrack;
 pub mod bot;
 pub mod fu;
 pub mod hand;
+pub mod inference;
+pub mod notation;
+pub mod points;
+pub mod rules;
+pub mod sampling;
 pub mod score;
 pub mod search;
+pub mod seed;
+pub mod shanten;
+pub mod strategy;
 pub mod tiles;
 pub mod yaku;
 
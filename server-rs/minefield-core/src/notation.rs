@@ -0,0 +1,211 @@
+// Text notation for tiles and hands.
+//
+// The compact notation writes numbers followed by a suit letter: m (man),
+// p (pin), s (sou) and z (honors: 1-4 winds, 5-7 dragons), for example
+// "123m456p789s11z".
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::tiles::{Suit, Tile, TileSet};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ParseTileError(String);
+
+impl fmt::Display for ParseTileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cannot parse tiles: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseTileError {}
+
+impl Suit {
+    pub fn letter(self) -> char {
+        match self {
+            Suit::Man => 'm',
+            Suit::Pin => 'p',
+            Suit::Sou => 's',
+            Suit::Honor => 'z',
+        }
+    }
+
+    pub fn from_letter(c: char) -> Option<Suit> {
+        match c {
+            'm' => Some(Suit::Man),
+            'p' => Some(Suit::Pin),
+            's' => Some(Suit::Sou),
+            'z' => Some(Suit::Honor),
+            _ => None,
+        }
+    }
+
+    // Letters used by Tile serialization ("M1", "X5")
+    fn from_upper_letter(c: char) -> Option<Suit> {
+        match c {
+            'M' => Some(Suit::Man),
+            'P' => Some(Suit::Pin),
+            'S' => Some(Suit::Sou),
+            'X' => Some(Suit::Honor),
+            _ => None,
+        }
+    }
+}
+
+impl Tile {
+    pub fn unicode(self) -> char {
+        use Tile::*;
+        let code = match self {
+            X1 | X2 | X3 | X4 => 0x1F000 + (self as u32 - X1 as u32),
+            // Unicode order is red, green, white
+            X5 => 0x1F006,
+            X6 => 0x1F005,
+            X7 => 0x1F004,
+            _ => match self.suit() {
+                Suit::Man => 0x1F007 + (self as u32 - M1 as u32),
+                Suit::Sou => 0x1F010 + (self as u32 - S1 as u32),
+                _ => 0x1F019 + (self as u32 - P1 as u32),
+            },
+        };
+        std::char::from_u32(code).unwrap()
+    }
+}
+
+impl fmt::Display for Tile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.number(), self.suit().letter())
+    }
+}
+
+/// Parses a single tile, either in compact ("5z") or serialized ("X5")
+/// notation.
+impl FromStr for Tile {
+    type Err = ParseTileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let chars: Vec<char> = s.chars().collect();
+        let parsed = match chars.as_slice() {
+            [n, c] if n.is_ascii_digit() => Suit::from_letter(*c).map(|suit| (suit, *n)),
+            [c, n] if n.is_ascii_digit() => Suit::from_upper_letter(*c).map(|suit| (suit, *n)),
+            _ => None,
+        };
+        parsed
+            .and_then(|(suit, n)| Tile::from_suit(suit, n.to_digit(10).unwrap() as usize))
+            .ok_or_else(|| ParseTileError(format!("invalid tile: {:?}", s)))
+    }
+}
+
+/// Parses a list of tiles in compact notation, keeping their order.
+/// Whitespace is ignored.
+pub fn parse_tiles(s: &str) -> Result<Vec<Tile>, ParseTileError> {
+    let mut result = vec![];
+    let mut numbers = vec![];
+    for c in s.chars() {
+        if c.is_whitespace() {
+            continue;
+        }
+        if let Some(n) = c.to_digit(10) {
+            numbers.push(n as usize);
+        } else if let Some(suit) = Suit::from_letter(c) {
+            if numbers.is_empty() {
+                return Err(ParseTileError(format!("no numbers before {:?}", c)));
+            }
+            for n in numbers.drain(..) {
+                let tile = Tile::from_suit(suit, n)
+                    .ok_or_else(|| ParseTileError(format!("invalid tile: {}{}", n, c)))?;
+                result.push(tile);
+            }
+        } else {
+            return Err(ParseTileError(format!("unexpected character {:?}", c)));
+        }
+    }
+    if !numbers.is_empty() {
+        return Err(ParseTileError("missing suit at the end".to_owned()));
+    }
+    Ok(result)
+}
+
+/// Formats a list of tiles in compact notation, keeping their order.
+pub fn format_tiles(tiles: &[Tile]) -> String {
+    let mut result = String::new();
+    for (i, tile) in tiles.iter().enumerate() {
+        result.push_str(&tile.number().to_string());
+        if i + 1 == tiles.len() || tiles[i + 1].suit() != tile.suit() {
+            result.push(tile.suit().letter());
+        }
+    }
+    result
+}
+
+/// Formats a list of tiles using Unicode mahjong tiles.
+pub fn format_unicode(tiles: &[Tile]) -> String {
+    tiles.iter().map(|tile| tile.unicode()).collect()
+}
+
+/// Formats the tiles in compact notation, sorted.
+impl fmt::Display for TileSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut tiles = vec![];
+        for tile in self.distinct() {
+            for _ in 0..self.get(tile) {
+                tiles.push(tile);
+            }
+        }
+        write!(f, "{}", format_tiles(&tiles))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tiles::Tile::*;
+
+    #[test]
+    fn test_tile() {
+        assert_eq!(M1.to_string(), "1m");
+        assert_eq!(P9.to_string(), "9p");
+        assert_eq!(X5.to_string(), "5z");
+
+        for tile in Tile::all() {
+            assert_eq!(tile.to_string().parse::<Tile>(), Ok(tile));
+        }
+        assert_eq!("S3".parse::<Tile>(), Ok(S3));
+        assert_eq!("X7".parse::<Tile>(), Ok(X7));
+        assert!("8z".parse::<Tile>().is_err());
+        assert!("0m".parse::<Tile>().is_err());
+        assert!("m1".parse::<Tile>().is_err());
+        assert!("11m".parse::<Tile>().is_err());
+    }
+
+    #[test]
+    fn test_parse_tiles() {
+        assert_eq!(
+            parse_tiles("123m456p789s11z"),
+            Ok(vec![M1, M2, M3, P4, P5, P6, S7, S8, S9, X1, X1])
+        );
+        assert_eq!(parse_tiles("5z 1m 5z"), Ok(vec![X5, M1, X5]));
+        assert_eq!(parse_tiles(""), Ok(vec![]));
+        assert!(parse_tiles("123").is_err());
+        assert!(parse_tiles("m").is_err());
+        assert!(parse_tiles("89z").is_err());
+        assert!(parse_tiles("12x").is_err());
+    }
+
+    #[test]
+    fn test_format_tiles() {
+        let tiles = [M1, M2, M3, P4, P5, P6, S7, S8, S9, X1, X1];
+        assert_eq!(format_tiles(&tiles), "123m456p789s11z");
+        assert_eq!(format_tiles(&[X5, M1, X5]), "5z1m5z");
+        assert_eq!(format_tiles(&[]), "");
+
+        let tiles = [S2, M9, M1, X2, S2];
+        assert_eq!(parse_tiles(&format_tiles(&tiles)), Ok(tiles.to_vec()));
+        assert_eq!(TileSet::from_tiles(&tiles).to_string(), "19m22s2z");
+    }
+
+    #[test]
+    fn test_format_unicode() {
+        assert_eq!(format_unicode(&[M1, M9, P1, S1, S9]), "🀇🀏🀙🀐🀘");
+        assert_eq!(format_unicode(&[X1, X4, X5, X6, X7]), "🀀🀃🀆🀅🀄");
+    }
+}
@@ -0,0 +1,135 @@
+use crate::rules::Ruleset;
+
+pub const MANGAN: usize = 2000;
+pub const YAKUMAN: usize = 8000;
+
+/// Basic points of a hand, before multiplying for ron or tsumo.
+///
+/// `fan` includes dora, `yakuman` is the number of yakuman in the hand
+/// (in which case `fan` and `fu` are ignored).
+pub fn basic_points(fan: usize, fu: usize, yakuman: usize, rules: &Ruleset) -> usize {
+    if yakuman > 0 {
+        let count = if rules.multiple_yakuman { yakuman } else { 1 };
+        return YAKUMAN * count;
+    }
+
+    match fan {
+        0 => 0,
+        13..=usize::MAX if rules.kazoe_yakuman => YAKUMAN,
+        11..=usize::MAX => MANGAN * 3,
+        8..=10 => MANGAN * 2,
+        6..=7 => MANGAN * 3 / 2,
+        5 => MANGAN,
+        _ => {
+            let points = fu << (fan + 2);
+            if points >= MANGAN || (rules.kiriage_mangan && points == 1920) {
+                MANGAN
+            } else {
+                points
+            }
+        }
+    }
+}
+
+fn round_up(points: usize) -> usize {
+    points.div_ceil(100) * 100
+}
+
+/// Points paid by the discarding player.
+pub fn ron_points(basic: usize, dealer: bool) -> usize {
+    if dealer {
+        round_up(basic * 6)
+    } else {
+        round_up(basic * 4)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TsumoPoints {
+    /// Paid by the dealer (0 if the winner is the dealer)
+    pub dealer: usize,
+    /// Paid by each non-dealer
+    pub non_dealer: usize,
+}
+
+/// Points paid by other players for a self-drawn win.
+pub fn tsumo_points(basic: usize, dealer: bool) -> TsumoPoints {
+    if dealer {
+        TsumoPoints {
+            dealer: 0,
+            non_dealer: round_up(basic * 2),
+        }
+    } else {
+        TsumoPoints {
+            dealer: round_up(basic * 2),
+            non_dealer: round_up(basic),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ron(fan: usize, fu: usize, dealer: bool) -> usize {
+        ron_points(basic_points(fan, fu, 0, &Ruleset::default()), dealer)
+    }
+
+    fn tsumo(fan: usize, fu: usize, dealer: bool) -> (usize, usize) {
+        let points = tsumo_points(basic_points(fan, fu, 0, &Ruleset::default()), dealer);
+        (points.dealer, points.non_dealer)
+    }
+
+    #[test]
+    fn test_ron() {
+        assert_eq!(ron(1, 30, false), 1000);
+        assert_eq!(ron(2, 30, false), 2000);
+        assert_eq!(ron(3, 30, false), 3900);
+        assert_eq!(ron(4, 30, false), 7700);
+        assert_eq!(ron(3, 60, false), 7700);
+        assert_eq!(ron(2, 25, false), 1600);
+        assert_eq!(ron(3, 70, false), 8000);
+        assert_eq!(ron(1, 30, true), 1500);
+        assert_eq!(ron(4, 30, true), 11600);
+        assert_eq!(ron(2, 40, true), 3900);
+    }
+
+    #[test]
+    fn test_tsumo() {
+        assert_eq!(tsumo(1, 30, false), (500, 300));
+        assert_eq!(tsumo(4, 30, false), (3900, 2000));
+        assert_eq!(tsumo(1, 30, true), (0, 500));
+        assert_eq!(tsumo(3, 40, true), (0, 2600));
+        assert_eq!(tsumo(5, 30, false), (4000, 2000));
+    }
+
+    #[test]
+    fn test_limits() {
+        assert_eq!(ron(5, 30, false), 8000);
+        assert_eq!(ron(6, 30, false), 12000);
+        assert_eq!(ron(7, 30, true), 18000);
+        assert_eq!(ron(8, 30, false), 16000);
+        assert_eq!(ron(11, 30, false), 24000);
+        assert_eq!(ron(13, 30, false), 32000);
+        assert_eq!(ron(20, 30, true), 48000);
+    }
+
+    #[test]
+    fn test_options() {
+        let rules = Ruleset {
+            kiriage_mangan: true,
+            kazoe_yakuman: false,
+            multiple_yakuman: false,
+            ..Ruleset::default()
+        };
+        assert_eq!(basic_points(4, 30, 0, &rules), MANGAN);
+        assert_eq!(basic_points(3, 60, 0, &rules), MANGAN);
+        assert_eq!(basic_points(3, 50, 0, &rules), 1600);
+        assert_eq!(basic_points(13, 30, 0, &rules), MANGAN * 3);
+        assert_eq!(basic_points(0, 0, 2, &rules), YAKUMAN);
+
+        let rules = Ruleset::default();
+        assert_eq!(basic_points(0, 0, 2, &rules), YAKUMAN * 2);
+        assert_eq!(basic_points(4, 30, 0, &rules), 1920);
+    }
+}
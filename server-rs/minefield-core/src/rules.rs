@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+
+use crate::tiles::Tile;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scoring {
+    /// Fixed points for each limit (`base_points`), nothing below mangan
+    Limit,
+    /// Points computed from fan and fu (see `points` module)
+    Full,
+}
+
+/// Game rules that can be changed between rooms.
+///
+/// Time limits are in seconds (beats). All fields have defaults, so a
+/// ruleset can be deserialized from a partial description.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Ruleset {
+    /// Number of tiles each player chooses their hand from
+    pub player_tiles: usize,
+    /// Number of discards for each player
+    pub discards: usize,
+    pub hand_time_limit: usize,
+    pub discard_time_limit: usize,
+    /// Time allowed for network lag, on top of time limits
+    pub extra_time: usize,
+    /// Winds of the east player and the other player
+    pub player_winds: [Tile; 2],
+    /// Minimum limit (see `Score::limit`) needed to win
+    pub min_limit: usize,
+    pub scoring: Scoring,
+    /// Points for each limit, for `Scoring::Limit`
+    pub base_points: [usize; 7],
+    /// Round 4 fan 30 fu and 3 fan 60 fu up to mangan, for `Scoring::Full`
+    /// (the `Scoring::Limit` table always does)
+    pub kiriage_mangan: bool,
+    /// Count 13 or more fan as yakuman, for `Scoring::Full`
+    pub kazoe_yakuman: bool,
+    /// Count each yakuman separately, for `Scoring::Full`
+    pub multiple_yakuman: bool,
+    pub uradora: bool,
+    pub ippatsu: bool,
+    pub hotei: bool,
+    /// Number of hands in a match (1 for a single game)
+    pub hands: usize,
+    /// Repeat the hand (with the same east) after a draw
+    pub repeat_on_draw: bool,
+    /// Bonus points for each draw since the last win
+    pub honba_points: usize,
+    /// Time between hands in a match
+    pub next_hand_delay: usize,
+}
+
+impl Default for Ruleset {
+    fn default() -> Self {
+        Ruleset {
+            player_tiles: 34,
+            discards: 17,
+            hand_time_limit: 3 * 60,
+            discard_time_limit: 15,
+            extra_time: 10,
+            player_winds: [Tile::X1, Tile::X3],
+            min_limit: 1,
+            scoring: Scoring::Limit,
+            base_points: [0, 8000, 12000, 16000, 24000, 32000, 64000],
+            kiriage_mangan: false,
+            kazoe_yakuman: true,
+            multiple_yakuman: true,
+            uradora: true,
+            ippatsu: true,
+            hotei: true,
+            hands: 1,
+            repeat_on_draw: false,
+            honba_points: 300,
+            next_hand_delay: 10,
+        }
+    }
+}
+
+impl Ruleset {
+    pub fn player_wind(&self, is_east: bool) -> Tile {
+        if is_east {
+            self.player_winds[0]
+        } else {
+            self.player_winds[1]
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), &'static str> {
+        // 2 players, dora indicator, uradora indicator
+        if self.player_tiles * 2 + 2 > 136 {
+            return Err("too many player tiles");
+        }
+        if self.player_tiles < 13 + self.discards {
+            return Err("not enough player tiles");
+        }
+        if self.discards == 0 {
+            return Err("no discards");
+        }
+        if self.min_limit >= self.base_points.len() {
+            return Err("min limit too high");
+        }
+        if self.hands == 0 {
+            return Err("no hands");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    extern crate serde_json;
+
+    #[test]
+    fn test_partial() {
+        let rules: Ruleset = serde_json::from_str(r#"{"discards": 12, "uradora": false}"#).unwrap();
+        assert_eq!(
+            rules,
+            Ruleset {
+                discards: 12,
+                uradora: false,
+                ..Ruleset::default()
+            }
+        );
+        assert!(rules.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate() {
+        let rules = Ruleset {
+            player_tiles: 20,
+            ..Ruleset::default()
+        };
+        assert_eq!(rules.validate(), Err("not enough player tiles"));
+        let rules = Ruleset {
+            player_tiles: 68,
+            ..Ruleset::default()
+        };
+        assert_eq!(rules.validate(), Err("too many player tiles"));
+    }
+}
@@ -0,0 +1,310 @@
+// Monte Carlo evaluation of discards.
+//
+// We draw possible opponent hands from the tiles we haven't seen, keep the
+// ones the opponent could actually be holding (tenpai, able to win, not
+// waiting on anything discarded so far), and count how often and how
+// expensively each of our candidate discards would deal in.
+
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::bot::BotState;
+use crate::score::Score;
+use crate::search::{find_all_waits, search};
+use crate::tiles::{Suit, Tile, TileSet};
+use crate::yaku::Yaku;
+
+// Attempts at drawing a single group before giving up on the hand.
+const GROUP_ATTEMPTS: usize = 10;
+
+// How often (1 in N) to draw a seven pairs hand instead of a normal one.
+const PAIRS_ODDS: u32 = 20;
+
+// Per discard. The server thinks away from the game, but the other player
+// still waits for the move.
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    // Number of opponent hands drawn, including rejected ones
+    pub iterations: usize,
+    // None to stop on iterations only, so that results depend on the seed
+    // alone
+    pub time: Option<Duration>,
+}
+
+impl Default for Budget {
+    fn default() -> Self {
+        Budget {
+            iterations: 200,
+            time: Some(Duration::from_millis(50)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DiscardRisk {
+    pub tile: Tile,
+    // Fraction of sampled hands that win on the tile
+    pub deal_in: f64,
+    // Average points paid, over all sampled hands
+    pub expected_loss: f64,
+}
+
+pub struct Sampler {
+    rng: StdRng,
+    budget: Budget,
+}
+
+impl Sampler {
+    pub fn new(seed: u64, budget: Budget) -> Self {
+        Sampler {
+            rng: StdRng::seed_from_u64(seed),
+            budget,
+        }
+    }
+
+    // Risk for each of the candidates, in the same order, scored with the
+    // room's rules. With no sampled hands (out of time, or nothing
+    // consistent), all risks are 0.
+    pub fn evaluate(&mut self, state: &BotState, candidates: &[Tile]) -> Vec<DiscardRisk> {
+        let unseen = state.unseen();
+        let discarded: HashSet<Tile> = state
+            .discards
+            .iter()
+            .chain(state.opponent_discards.iter())
+            .cloned()
+            .collect();
+        let opponent_east = state.player_wind != state.rules.player_winds[0];
+
+        let mut losses = vec![(0, 0); candidates.len()];
+        let mut samples = 0;
+        let start = Instant::now();
+        for _ in 0..self.budget.iterations {
+            match self.budget.time {
+                Some(time) if start.elapsed() >= time => break,
+                _ => (),
+            }
+            let hand = match self.sample_hand(&unseen) {
+                Some(hand) => hand,
+                None => continue,
+            };
+            let waits = match self.winning_waits(state, &hand, opponent_east) {
+                Some(waits) => waits,
+                None => continue,
+            };
+            if waits.iter().any(|(wait, _)| discarded.contains(wait)) {
+                // already won, or furiten
+                continue;
+            }
+
+            samples += 1;
+            for (i, tile) in candidates.iter().enumerate() {
+                if let Some((_, points)) = waits.iter().find(|(wait, _)| wait == tile) {
+                    losses[i].0 += 1;
+                    losses[i].1 += points;
+                }
+            }
+        }
+
+        let total = std::cmp::max(samples, 1) as f64;
+        candidates
+            .iter()
+            .zip(losses.iter())
+            .map(|(tile, (count, points))| DiscardRisk {
+                tile: *tile,
+                deal_in: *count as f64 / total,
+                expected_loss: *points as f64 / total,
+            })
+            .collect()
+    }
+
+    // Waits of the hand, with points for a ron on each of them. Waits not
+    // reaching the minimum limit are included with 0 points, because they
+    // would still make the opponent furiten. None if the hand cannot win at
+    // all.
+    fn winning_waits(
+        &self,
+        state: &BotState,
+        hand: &[Tile],
+        opponent_east: bool,
+    ) -> Option<Vec<(Tile, usize)>> {
+        let rules = &state.rules;
+        let player_wind = rules.player_wind(opponent_east);
+        let mut tiles = hand.to_vec();
+        let mut result = vec![];
+        let mut can_win = false;
+        for wait in find_all_waits(hand) {
+            tiles.push(wait);
+            let points = search(&tiles, wait)
+                .iter()
+                .filter_map(|hand| {
+                    let mut score = Score::from_hand(hand, player_wind, &[Yaku::Riichi]);
+                    score.add_dora(state.dora);
+                    if score.limit(rules) < rules.min_limit {
+                        return None;
+                    }
+                    Some(score.points(rules, opponent_east))
+                })
+                .max();
+            tiles.pop();
+
+            can_win = can_win || points.is_some();
+            result.push((wait, points.unwrap_or(0)));
+        }
+        if can_win {
+            Some(result)
+        } else {
+            None
+        }
+    }
+
+    // A random tenpai hand: a complete hand with one tile removed.
+    fn sample_hand(&mut self, unseen: &TileSet) -> Option<Vec<Tile>> {
+        let mut left = unseen.clone();
+        let mut tiles = vec![];
+        if self.rng.gen_range(0, PAIRS_ODDS) == 0 {
+            for _ in 0..7 {
+                let pair = self.sample_pair(&left)?;
+                // no repeated pairs in seven pairs
+                left.add(pair, -left.get(pair));
+                tiles.push(pair);
+                tiles.push(pair);
+            }
+        } else {
+            for _ in 0..4 {
+                let group = self.sample_group(&left)?;
+                left.add_all(&group, -1);
+                tiles.extend(group);
+            }
+            let pair = self.sample_pair(&left)?;
+            tiles.push(pair);
+            tiles.push(pair);
+        }
+        tiles.remove(self.rng.gen_range(0, tiles.len()));
+        tiles.sort();
+        Some(tiles)
+    }
+
+    fn sample_pair(&mut self, left: &TileSet) -> Option<Tile> {
+        let mut pairs = TileSet::new();
+        for tile in left.distinct() {
+            if left.get(tile) >= 2 {
+                pairs.add(tile, left.get(tile));
+            }
+        }
+        self.sample_tile(&pairs)
+    }
+
+    // Picks a tile, then one of the pons or chis it could be part of.
+    fn sample_group(&mut self, left: &TileSet) -> Option<Vec<Tile>> {
+        for _ in 0..GROUP_ATTEMPTS {
+            let tile = self.sample_tile(left)?;
+            let mut groups = vec![];
+            if left.get(tile) >= 3 {
+                groups.push(vec![tile, tile, tile]);
+            }
+            if tile.suit() != Suit::Honor {
+                let n = tile.number();
+                for first in n.saturating_sub(2).max(1)..=n {
+                    let chi: Option<Vec<Tile>> = (first..first + 3)
+                        .map(|m| Tile::from_suit(tile.suit(), m).filter(|t| left.get(*t) > 0))
+                        .collect();
+                    if let Some(chi) = chi {
+                        groups.push(chi);
+                    }
+                }
+            }
+            if !groups.is_empty() {
+                let i = self.rng.gen_range(0, groups.len());
+                return Some(groups.swap_remove(i));
+            }
+        }
+        None
+    }
+
+    // A random tile, weighted by the number of copies.
+    fn sample_tile(&mut self, tiles: &TileSet) -> Option<Tile> {
+        let total: isize = tiles.distinct().map(|t| tiles.get(t)).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut n = self.rng.gen_range(0, total);
+        for tile in tiles.distinct() {
+            n -= tiles.get(tile);
+            if n < 0 {
+                return Some(tile);
+            }
+        }
+        unreachable!()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use Tile::*;
+
+    const TILES: [Tile; 34] = [
+        M2, M3, M5, M6, M7, M7, M8, M9, M9, P1, P3, P5, P6, P6, P7, P8, S1, S2, S2, S3, S4, S6, S7,
+        S7, S8, X1, X2, X2, X4, X4, X4, X5, X6, X7,
+    ];
+
+    fn budget() -> Budget {
+        Budget {
+            iterations: 200,
+            time: None,
+        }
+    }
+
+    #[test]
+    fn test_sample_hand() {
+        let state = BotState::new(&TILES, X4, X3);
+        let unseen = state.unseen();
+        let mut sampler = Sampler::new(1, budget());
+        for _ in 0..20 {
+            if let Some(hand) = sampler.sample_hand(&unseen) {
+                assert_eq!(hand.len(), 13);
+                assert!(unseen.contains(&TileSet::from_tiles(&hand)));
+                assert!(!find_all_waits(&hand).is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let mut state = BotState::new(&TILES, X4, X3);
+        state.opponent_discards = vec![P2, P2, M4];
+        state.discards = vec![S1];
+
+        let candidates = [P2, M4, S1, X4, M5];
+        let mut sampler = Sampler::new(1, budget());
+        let risks = sampler.evaluate(&state, &candidates);
+        assert_eq!(risks.len(), candidates.len());
+        for risk in risks.iter() {
+            assert!(0.0 <= risk.deal_in && risk.deal_in <= 1.0);
+            assert!(risk.expected_loss >= 8000.0 * risk.deal_in);
+        }
+
+        // discarded tiles are safe, last copy of a honor too
+        assert_eq!(risks[0].deal_in, 0.0);
+        assert_eq!(risks[1].deal_in, 0.0);
+        assert_eq!(risks[2].deal_in, 0.0);
+        assert_eq!(risks[3].deal_in, 0.0);
+
+        // same seed, same result
+        let mut sampler = Sampler::new(1, budget());
+        assert_eq!(sampler.evaluate(&state, &candidates), risks);
+
+        // scored with the room's rules
+        for points in state.rules.base_points.iter_mut() {
+            *points *= 2;
+        }
+        let mut sampler = Sampler::new(1, budget());
+        for (doubled, risk) in sampler.evaluate(&state, &candidates).iter().zip(risks) {
+            assert_eq!(doubled.deal_in, risk.deal_in);
+            assert_eq!(doubled.expected_loss, 2.0 * risk.expected_loss);
+        }
+    }
+}
@@ -1,15 +1,16 @@
-use crate::fu::fu;
+use crate::fu::{fu, FuBreakdown};
 use crate::hand::Hand;
+use crate::points::{basic_points, ron_points};
+use crate::rules::{Ruleset, Scoring};
 use crate::tiles::Tile;
 use crate::yaku::{yaku, Yaku};
 
-const BASE_POINTS: [usize; 7] = [0, 8000, 12000, 16000, 24000, 32000, 64000];
-
 #[derive(Debug)]
 pub struct Score {
     pub yaku: Vec<Yaku>,
     pub tiles: Vec<Tile>,
     pub fu: usize,
+    pub fu_breakdown: FuBreakdown,
     pub dora_count: usize,
 }
 
@@ -17,11 +18,12 @@ impl Score {
     pub fn from_hand(hand: &Hand, player_wind: Tile, special: &[Yaku]) -> Self {
         let tiles = hand.tiles();
         let yaku = yaku(hand, player_wind, special);
-        let fu = fu(hand, player_wind);
+        let fu_breakdown = fu(hand, player_wind);
         Score {
             yaku,
             tiles,
-            fu,
+            fu: fu_breakdown.total,
+            fu_breakdown,
             dora_count: 0,
         }
     }
@@ -49,7 +51,15 @@ impl Score {
         self.yaku.iter().map(|y| y.fan()).sum()
     }
 
-    pub fn limit(&self) -> usize {
+    pub fn yaku_fan(&self) -> Vec<(Yaku, usize)> {
+        self.yaku.iter().map(|y| (*y, y.fan())).collect()
+    }
+
+    /// Mangan or above, as an index into `Ruleset::base_points`.
+    pub fn limit(&self, rules: &Ruleset) -> usize {
+        // the limit table always rounds these up
+        let kiriage = rules.kiriage_mangan || rules.scoring == Scoring::Limit;
+        let (min_fu_3, min_fu_4) = if kiriage { (60, 30) } else { (70, 40) };
         let mut fan = self.fan();
         if fan < 13 {
             fan = std::cmp::min(13, fan + self.dora_count);
@@ -58,8 +68,8 @@ impl Score {
         match fan {
             // no mangan
             0..=2 => 0,
-            3 if self.fu < 60 => 0,
-            4 if self.fu < 30 => 0,
+            3 if self.fu < min_fu_3 => 0,
+            4 if self.fu < min_fu_4 => 0,
             // mangan
             3..=5 => 1,
             // haneman
@@ -75,7 +85,75 @@ impl Score {
         }
     }
 
-    pub fn points(&self) -> usize {
-        BASE_POINTS[self.limit()]
+    pub fn yakuman_count(&self) -> usize {
+        self.yaku.iter().filter(|y| y.fan() == 13).count()
+    }
+
+    pub fn basic_points(&self, rules: &Ruleset) -> usize {
+        let yakuman = self.yakuman_count();
+        basic_points(self.fan() + self.dora_count, self.fu, yakuman, rules)
+    }
+
+    /// Points for ron.
+    pub fn points(&self, rules: &Ruleset, dealer: bool) -> usize {
+        match rules.scoring {
+            Scoring::Limit => rules.base_points[self.limit(rules)],
+            Scoring::Full => ron_points(self.basic_points(rules), dealer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::search;
+    use crate::tiles::Tile::*;
+
+    fn score_hand(tiles: &[Tile; 14], wait: Tile) -> Score {
+        let hands = search(tiles, wait);
+        assert_eq!(hands.len(), 1);
+        Score::from_hand(&hands[0], X1, &[Yaku::Riichi])
+    }
+
+    #[test]
+    fn test_points() {
+        let full = Ruleset {
+            scoring: Scoring::Full,
+            ..Ruleset::default()
+        };
+
+        // riichi pinfu tanyao, 30 fu
+        let score = score_hand(
+            &[M2, M3, M4, P3, P4, P5, S2, S3, S4, S5, S6, S7, S8, S8],
+            S7,
+        );
+        assert_eq!(score.limit(&Ruleset::default()), 0);
+        assert_eq!(score.points(&Ruleset::default(), false), 0);
+        assert_eq!(score.points(&full, false), 3900);
+        assert_eq!(score.points(&full, true), 5800);
+
+        // with a dora: 4 fan 30 fu, mangan only with kiriage
+        let mut score = score;
+        score.dora_count = 1;
+        assert_eq!(score.limit(&Ruleset::default()), 1);
+        assert_eq!(score.points(&Ruleset::default(), false), 8000);
+        assert_eq!(score.limit(&full), 0);
+        assert_eq!(score.points(&full, false), 7700);
+        let kiriage = Ruleset {
+            kiriage_mangan: true,
+            ..full.clone()
+        };
+        assert_eq!(score.limit(&kiriage), 1);
+        assert_eq!(score.points(&kiriage, false), 8000);
+
+        // riichi kokushi is a yakuman
+        let score = score_hand(
+            &[M1, M9, P1, P9, S1, S9, S9, X1, X2, X3, X4, X5, X6, X7],
+            M1,
+        );
+        assert_eq!(score.yakuman_count(), 1);
+        assert_eq!(score.points(&Ruleset::default(), false), 32000);
+        assert_eq!(score.points(&full, false), 32000);
+        assert_eq!(score.points(&full, true), 48000);
     }
 }
@@ -0,0 +1,64 @@
+// Reproducible randomness.
+//
+// A `Seeder` hands out seeded RNGs one after another. It is serialized as
+// just two numbers, so that a restored object continues the same sequence,
+// and the same starting seed (with the same inputs) always gives the same
+// results.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Seeder {
+    // starting seed, for reproducing
+    seed: u64,
+    next: u64,
+}
+
+impl Seeder {
+    pub fn new(seed: u64) -> Self {
+        Seeder { seed, next: seed }
+    }
+
+    pub fn from_entropy() -> Self {
+        Self::new(rand::random())
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // Seed for something that keeps its own RNG (a game, a bot).
+    pub fn next_seed(&mut self) -> u64 {
+        self.rng().gen()
+    }
+
+    pub fn rng(&mut self) -> StdRng {
+        let mut rng = StdRng::seed_from_u64(self.next);
+        self.next = rng.gen();
+        rng
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_seeder() {
+        let mut seeder = Seeder::new(42);
+        let a: Vec<u64> = (0..3).map(|_| seeder.next_seed()).collect();
+        assert_eq!(seeder.seed(), 42);
+
+        let mut seeder = Seeder::new(42);
+        assert_eq!(seeder.next_seed(), a[0]);
+        // restored in the middle
+        let json = serde_json::to_string(&seeder).unwrap();
+        let mut seeder: Seeder = serde_json::from_str(&json).unwrap();
+        assert_eq!(seeder.next_seed(), a[1]);
+        assert_eq!(seeder.next_seed(), a[2]);
+
+        assert_ne!(Seeder::new(43).next_seed(), a[0]);
+    }
+}
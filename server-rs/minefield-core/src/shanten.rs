@@ -0,0 +1,209 @@
+use crate::backtrack::{Backtrack, BacktrackStrategy};
+use crate::tiles::{Tile, TileSet};
+
+struct NormalShanten;
+
+impl BacktrackStrategy for NormalShanten {
+    type Item = isize;
+
+    fn generate(&self, bt: &Backtrack) -> Vec<Vec<Tile>> {
+        // Always use up the lowest tile first, so that every decomposition is
+        // visited only once.
+        let first = match bt.tiles.distinct().next() {
+            Some(tile) => tile,
+            None => return vec![],
+        };
+
+        let mut parts = bt.find_groups();
+        parts.append(&mut bt.find_incomplete_groups());
+        parts.push(vec![first]);
+        parts.retain(|part| part[0] == first);
+        parts
+    }
+
+    fn check(&self, bt: &Backtrack) -> Vec<isize> {
+        let mut groups = 0;
+        let mut pairs = 0;
+        let mut incomplete = 0;
+        for part in bt.stack.iter() {
+            match part.len() {
+                3 => groups += 1,
+                2 if part[0] == part[1] => pairs += 1,
+                2 => incomplete += 1,
+                _ => (),
+            }
+        }
+
+        // No pair, all incomplete groups count towards the 4 groups
+        let mut result = 8 - 2 * groups - std::cmp::min(pairs + incomplete, 4 - groups);
+        // One of the pairs used as the pair
+        if pairs > 0 {
+            let with_pair = 7 - 2 * groups - std::cmp::min(pairs - 1 + incomplete, 4 - groups);
+            result = std::cmp::min(result, with_pair);
+        }
+        vec![result]
+    }
+}
+
+fn check_size(tiles: &[Tile]) {
+    assert!(
+        tiles.len() == 13 || tiles.len() == 14,
+        "expected 13 or 14 tiles"
+    );
+}
+
+/// Shanten number of a 13-tile or 14-tile hand: the number of tiles
+/// it needs to be in tenpai. 0 means tenpai, -1 means a complete hand.
+pub fn shanten(tiles: &[Tile]) -> isize {
+    let normal = normal_shanten(tiles);
+    let pairs = pairs_shanten(tiles);
+    let kokushi = kokushi_shanten(tiles);
+    std::cmp::min(normal, std::cmp::min(pairs, kokushi))
+}
+
+/// Shanten number for a 4 groups + pair hand.
+pub fn normal_shanten(tiles: &[Tile]) -> isize {
+    check_size(tiles);
+    let mut bt = Backtrack::from_tiles(tiles, tiles.len());
+    bt.run(&NormalShanten).into_iter().min().unwrap()
+}
+
+/// Shanten number for chiitoitsu (7 distinct pairs).
+pub fn pairs_shanten(tiles: &[Tile]) -> isize {
+    check_size(tiles);
+    let tile_set = TileSet::from_tiles(tiles);
+    let mut pairs = 0;
+    let mut distinct = 0;
+    for tile in tile_set.distinct() {
+        distinct += 1;
+        if tile_set.get(tile) >= 2 {
+            pairs += 1;
+        }
+    }
+    6 - pairs + std::cmp::max(0, 7 - distinct)
+}
+
+/// Shanten number for kokushi musou (13 orphans).
+pub fn kokushi_shanten(tiles: &[Tile]) -> isize {
+    check_size(tiles);
+    let tile_set = TileSet::from_tiles(tiles);
+    let mut yaochu = 0;
+    let mut has_pair = false;
+    for tile in tile_set.distinct() {
+        if tile.is_yaochu() {
+            yaochu += 1;
+            if tile_set.get(tile) >= 2 {
+                has_pair = true;
+            }
+        }
+    }
+    13 - yaochu - if has_pair { 1 } else { 0 }
+}
+
+/// Tiles that would lower the shanten number of a 13-tile hand, together
+/// with the number of copies still available.
+///
+/// `visible` contains the tiles seen outside of the hand (discards, dora
+/// indicators etc.); these, and the tiles in hand, are not counted as
+/// available.
+pub fn ukeire(tiles: &[Tile], visible: &TileSet) -> Vec<(Tile, usize)> {
+    assert_eq!(tiles.len(), 13);
+    let current = shanten(tiles);
+    let hand_set = TileSet::from_tiles(tiles);
+
+    let mut result = vec![];
+    let mut tiles = tiles.to_vec();
+    for tile in Tile::all() {
+        if hand_set.get(tile) == 4 {
+            continue;
+        }
+        tiles.push(tile);
+        if shanten(&tiles) < current {
+            let count = 4 - hand_set.get(tile) - visible.get(tile);
+            result.push((tile, std::cmp::max(0, count) as usize));
+        }
+        tiles.pop();
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::find_all_waits;
+    use crate::tiles::Tile::*;
+
+    #[test]
+    fn test_complete() {
+        let tiles = [M1, M2, M3, P1, P2, P3, S1, S2, S3, S4, S5, S6, S7, S7];
+        assert_eq!(normal_shanten(&tiles), -1);
+        assert_eq!(shanten(&tiles), -1);
+
+        let tiles = [M1, M1, P3, P3, P4, P4, P5, P5, P7, P7, X1, X1, X3, X3];
+        assert_eq!(pairs_shanten(&tiles), -1);
+        assert_eq!(shanten(&tiles), -1);
+
+        let tiles = [M1, M9, P1, P9, S1, S9, S9, X1, X2, X3, X4, X5, X6, X7];
+        assert_eq!(kokushi_shanten(&tiles), -1);
+        assert_eq!(shanten(&tiles), -1);
+    }
+
+    #[test]
+    fn test_tenpai() {
+        let tiles = [M1, M2, M3, P1, P2, P3, S1, S2, S3, S4, S5, S6, S7];
+        assert_eq!(normal_shanten(&tiles), 0);
+
+        let tiles = [M1, M1, P3, P3, P4, P4, P5, P5, P7, P7, X1, X1, X3];
+        assert_eq!(pairs_shanten(&tiles), 0);
+
+        let tiles = [M1, M9, P1, P9, S1, S9, X1, X2, X3, X4, X5, X6, X7];
+        assert_eq!(kokushi_shanten(&tiles), 0);
+        assert_eq!(pairs_shanten(&tiles), 6);
+    }
+
+    #[test]
+    fn test_shanten() {
+        // 2 groups, 2 incomplete groups, pair
+        let tiles = [M1, M2, M3, M5, M7, P2, P3, P4, S4, S5, X1, X1, X5];
+        assert_eq!(normal_shanten(&tiles), 1);
+
+        // 3 groups, too many incomplete groups
+        let tiles = [M1, M2, M3, M5, M7, P2, P3, P4, S1, S2, S3, S5, S6];
+        assert_eq!(normal_shanten(&tiles), 1);
+
+        // pairs: 4 of a kind counts as one pair, and there are only 6 kinds
+        let tiles = [M1, M1, M1, M1, P3, P3, P4, P4, P5, P5, X1, X2, X2];
+        assert_eq!(pairs_shanten(&tiles), 2);
+
+        // nothing useful at all
+        let tiles = [M1, M4, M7, P2, P5, P8, S3, S6, S9, X1, X2, X3, X4];
+        assert_eq!(normal_shanten(&tiles), 8);
+        assert_eq!(pairs_shanten(&tiles), 6);
+        assert_eq!(kokushi_shanten(&tiles), 7);
+        assert_eq!(shanten(&tiles), 6);
+    }
+
+    #[test]
+    fn test_ukeire_tenpai() {
+        let tiles = [M1, M1, M1, M2, M3, M4, M5, M6, M7, M8, M9, M9, M9];
+        let result: Vec<Tile> = ukeire(&tiles, &TileSet::new())
+            .into_iter()
+            .map(|(tile, _)| tile)
+            .collect();
+        assert_eq!(result, find_all_waits(&tiles));
+    }
+
+    #[test]
+    fn test_ukeire_counts() {
+        // waiting on M4/M7, one M7 visible
+        let tiles = [M5, M6, P1, P2, P3, P4, P5, P6, S7, S8, S9, X1, X1];
+        let visible = TileSet::from_tiles(&[M7, S1]);
+        assert_eq!(ukeire(&tiles, &visible), vec![(M4, 4), (M7, 3)]);
+
+        // 1-shanten: needs one of the incomplete groups completed
+        let tiles = [M2, M4, P1, P2, P3, S1, S2, S3, S7, S8, X1, X1, X5];
+        assert_eq!(shanten(&tiles), 1);
+        let result = ukeire(&tiles, &TileSet::new());
+        assert_eq!(result, vec![(M3, 4), (S6, 4), (S9, 4)]);
+    }
+}
@@ -0,0 +1,339 @@
+// Bot strategies: how to choose the hand and the discards.
+//
+// Strategies are selected by name (see `by_name`), and only the name is
+// serialized together with the bot. Anything a strategy learns during the
+// game that has to survive a restart should be kept in `BotState`.
+
+use log::{info, warn};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::bot::BotState;
+use crate::inference::Observation;
+use crate::sampling::{Budget, Sampler};
+use crate::tiles::Tile;
+
+pub trait Strategy: Send {
+    fn name(&self) -> &'static str;
+
+    // Any randomness should come from `rng`, so that games can be
+    // reproduced.
+    fn choose_hand(&mut self, state: &BotState, rng: &mut StdRng) -> Vec<Tile>;
+
+    // Has to return one of the tiles left in `state.tile_set`.
+    fn choose_discard(&mut self, state: &BotState, rng: &mut StdRng) -> Tile;
+
+    // Called after the tile has been added to `state.safe_tiles`.
+    fn opponent_discard(&mut self, _state: &BotState, _tile: Tile) {}
+}
+
+pub const DEFAULT_STRATEGY: &str = "heuristic";
+
+pub const STRATEGY_NAMES: [&str; 5] = ["random", "heuristic", "defensive", "value", "sampling"];
+
+pub fn by_name(name: &str) -> Option<Box<dyn Strategy>> {
+    match name {
+        "random" => Some(Box::new(RandomStrategy)),
+        "heuristic" => Some(Box::new(HeuristicStrategy)),
+        "defensive" => Some(Box::new(DefensiveStrategy)),
+        "value" => Some(Box::new(ValueStrategy)),
+        "sampling" => Some(Box::new(SamplingStrategy::default())),
+        _ => None,
+    }
+}
+
+pub fn default_strategy() -> Box<dyn Strategy> {
+    by_name(DEFAULT_STRATEGY).unwrap()
+}
+
+// Any tenpai (or not even that), any discard. For beginners.
+pub struct RandomStrategy;
+
+impl Strategy for RandomStrategy {
+    fn name(&self) -> &'static str {
+        "random"
+    }
+
+    fn choose_hand(&mut self, state: &BotState, rng: &mut StdRng) -> Vec<Tile> {
+        match state.all_tenpai().choose(rng) {
+            Some(hand) => hand.clone(),
+            None => state.fallback_hand(),
+        }
+    }
+
+    fn choose_discard(&mut self, state: &BotState, rng: &mut StdRng) -> Tile {
+        let tiles: Vec<Tile> = state.tile_set.distinct().collect();
+        *tiles.choose(rng).unwrap()
+    }
+}
+
+// Tenpai with best chances of winning (and safest tiles left), discards: safe
+// tile, then most common one, then furiten.
+pub struct HeuristicStrategy;
+
+impl Strategy for HeuristicStrategy {
+    fn name(&self) -> &'static str {
+        "heuristic"
+    }
+
+    fn choose_hand(&mut self, state: &BotState, _rng: &mut StdRng) -> Vec<Tile> {
+        let danger = Observation::from_bot(state).danger_map();
+        match state.find_best_tenpai(|state, tiles| state.eval_tenpai_safe(tiles, &danger)) {
+            Some(hand) => {
+                info!("found a tenpai");
+                hand
+            }
+            None => state.fallback_hand(),
+        }
+    }
+
+    fn choose_discard(&mut self, state: &BotState, rng: &mut StdRng) -> Tile {
+        // safe tile, if any
+        let remaining_set = state.tile_set.as_hash_set();
+        let mut safe = remaining_set.intersection(&state.safe_tiles);
+        if let Some(tile) = safe.next() {
+            info!("found safe tile");
+            return *tile;
+        }
+
+        // most common (but not in our waits)
+        let mut most_common: Vec<Tile> = state.tile_set.distinct().collect();
+        most_common.shuffle(rng);
+        most_common.sort_by_key(|t| state.tile_set.get(*t));
+        for tile in most_common.iter() {
+            if !state.waits.contains(tile) {
+                info!("found common tile");
+                return *tile;
+            }
+        }
+
+        // furiten ahoy
+        assert!(!most_common.is_empty());
+        warn!("furiten!");
+        most_common[0]
+    }
+}
+
+// Same hand as the heuristic one, but discards the least dangerous tiles
+// first.
+pub struct DefensiveStrategy;
+
+impl Strategy for DefensiveStrategy {
+    fn name(&self) -> &'static str {
+        "defensive"
+    }
+
+    fn choose_hand(&mut self, state: &BotState, rng: &mut StdRng) -> Vec<Tile> {
+        HeuristicStrategy.choose_hand(state, rng)
+    }
+
+    fn choose_discard(&mut self, state: &BotState, rng: &mut StdRng) -> Tile {
+        let danger = Observation::from_bot(state).danger_map();
+        let mut tiles: Vec<Tile> = state.tile_set.distinct().collect();
+        tiles.shuffle(rng);
+        // not furiten, then least dangerous, then most common
+        tiles.sort_by(|a, b| {
+            let furiten = |t| state.waits.contains(t);
+            furiten(a)
+                .cmp(&furiten(b))
+                .then(danger.get(*a).partial_cmp(&danger.get(*b)).unwrap())
+                .then(state.tile_set.get(*b).cmp(&state.tile_set.get(*a)))
+        });
+        tiles[0]
+    }
+}
+
+// Goes for the most expensive hand, even with worse chances of winning.
+pub struct ValueStrategy;
+
+impl Strategy for ValueStrategy {
+    fn name(&self) -> &'static str {
+        "value"
+    }
+
+    fn choose_hand(&mut self, state: &BotState, _rng: &mut StdRng) -> Vec<Tile> {
+        match state.find_best_tenpai(eval_value) {
+            Some(hand) => hand,
+            None => state.fallback_hand(),
+        }
+    }
+
+    fn choose_discard(&mut self, state: &BotState, rng: &mut StdRng) -> Tile {
+        HeuristicStrategy.choose_discard(state, rng)
+    }
+}
+
+// Average limit over the winning waits, with number of waits as a tie
+// breaker.
+fn eval_value(state: &BotState, tiles: &[Tile]) -> Option<f64> {
+    let mut total = 0;
+    let mut good_count = 0;
+    for (_, count, max_score) in state.wait_scores(tiles) {
+        if max_score > 0 {
+            total += count * max_score as isize;
+            good_count += count;
+        }
+    }
+    if good_count == 0 {
+        return None;
+    }
+    Some(total as f64 / good_count as f64 + good_count as f64 / 100.0)
+}
+
+// Same hand as the heuristic one, discards the tile with the lowest expected
+// loss over sampled opponent hands. Only reproducible for a reproducible
+// bot, which samples without a time limit.
+#[derive(Default)]
+pub struct SamplingStrategy {
+    budget: Budget,
+}
+
+impl Strategy for SamplingStrategy {
+    fn name(&self) -> &'static str {
+        "sampling"
+    }
+
+    fn choose_hand(&mut self, state: &BotState, rng: &mut StdRng) -> Vec<Tile> {
+        HeuristicStrategy.choose_hand(state, rng)
+    }
+
+    fn choose_discard(&mut self, state: &BotState, rng: &mut StdRng) -> Tile {
+        // no need to sample for a safe tile
+        if let Some(tile) = state
+            .tile_set
+            .distinct()
+            .find(|t| state.safe_tiles.contains(t) && !state.waits.contains(t))
+        {
+            return tile;
+        }
+
+        let tiles: Vec<Tile> = state.tile_set.distinct().collect();
+        let mut budget = self.budget;
+        if state.reproducible {
+            budget.time = None;
+        }
+        let mut sampler = Sampler::new(rng.gen(), budget);
+        let mut risks = sampler.evaluate(state, &tiles);
+        // not furiten, then least expected loss, then most common
+        risks.sort_by(|a, b| {
+            let furiten = |t| state.waits.contains(t);
+            furiten(&a.tile)
+                .cmp(&furiten(&b.tile))
+                .then(a.expected_loss.partial_cmp(&b.expected_loss).unwrap())
+                .then(state.tile_set.get(b.tile).cmp(&state.tile_set.get(a.tile)))
+        });
+        info!("discard risk: {:?}", risks[0]);
+        risks[0].tile
+    }
+}
+
+// Serializes a strategy as its name.
+pub mod by_name {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    use super::Strategy;
+
+    #[allow(clippy::borrowed_box)]
+    pub fn serialize<S: Serializer>(
+        strategy: &Box<dyn Strategy>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(strategy.name())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Box<dyn Strategy>, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        super::by_name(&name)
+            .ok_or_else(|| de::Error::unknown_variant(&name, &super::STRATEGY_NAMES))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bot::Bot;
+    use crate::rules::Ruleset;
+    use rand::SeedableRng;
+    use Tile::*;
+
+    const TILES: [Tile; 34] = [
+        M2, M3, M5, M6, M7, M7, M8, M9, M9, P1, P3, P5, P6, P6, P7, P8, S1, S2, S2, S3, S4, S6, S7,
+        S7, S8, X1, X2, X2, X4, X4, X4, X5, X6, X7,
+    ];
+
+    #[test]
+    fn test_by_name() {
+        for name in STRATEGY_NAMES.iter() {
+            assert_eq!(by_name(name).unwrap().name(), *name);
+        }
+        assert!(by_name("clairvoyant").is_none());
+    }
+
+    #[test]
+    fn test_serialize() {
+        let bot = Bot::with_strategy(
+            &TILES,
+            X4,
+            X3,
+            &Ruleset::default(),
+            by_name("defensive").unwrap(),
+            1,
+        );
+        let json = serde_json::to_string(&bot).unwrap();
+        let bot: Bot = serde_json::from_str(&json).unwrap();
+        assert_eq!(bot.strategy_name(), "defensive");
+    }
+
+    #[test]
+    fn test_legal_moves() {
+        for name in STRATEGY_NAMES.iter() {
+            let mut bot = Bot::with_strategy(
+                &TILES,
+                X4,
+                X3,
+                &Ruleset::default(),
+                by_name(name).unwrap(),
+                1,
+            );
+            let hand = bot.choose_hand();
+            assert_eq!(hand.len(), 13);
+
+            bot.opponent_discard(P5);
+            let mut left: Vec<Tile> = TILES.to_vec();
+            for tile in hand.iter() {
+                let i = left.iter().position(|t| t == tile).unwrap();
+                left.remove(i);
+            }
+            for _ in 0..left.len() {
+                let tile = bot.choose_discard();
+                let i = left.iter().position(|t| *t == tile).unwrap();
+                left.remove(i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_defensive() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut state = BotState::new(&TILES, X4, X3);
+        state.safe_tiles.insert(S4);
+        state.opponent_discards.push(S4);
+        let danger = Observation::from_bot(&state).danger_map();
+        assert_eq!(danger.get(S4), 0.0);
+        // all copies visible: only kokushi waits on it
+        assert!(danger.get(X4) > 0.0);
+        assert!(danger.get(X4) < danger.get(M5) / 100.0);
+        // suji
+        assert!(danger.get(S1) < danger.get(M5));
+        assert!(danger.get(S7) < danger.get(M5));
+        assert!(danger.get(M1) < danger.get(M5));
+
+        // genbutsu, then the next safest
+        assert_eq!(DefensiveStrategy.choose_discard(&state, &mut rng), S4);
+        state.tile_set.add(S4, -state.tile_set.get(S4));
+        assert_eq!(DefensiveStrategy.choose_discard(&state, &mut rng), X4);
+    }
+}
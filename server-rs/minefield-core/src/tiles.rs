@@ -1,6 +1,6 @@
 use std::collections::HashSet;
 
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 
 #[derive(Debug, Copy, Clone, PartialEq, PartialOrd, Eq, Ord, Hash, Serialize, Deserialize)]
 #[repr(u8)]
@@ -83,6 +83,24 @@ impl Tile {
         self == X5 || self == X6 || self == X7 || self == player_wind
     }
 
+    pub fn number(self) -> usize {
+        (self as usize) % 9 + 1
+    }
+
+    pub fn from_suit(suit: Suit, number: usize) -> Option<Tile> {
+        let (first, count) = match suit {
+            Suit::Man => (M1, 9),
+            Suit::Pin => (P1, 9),
+            Suit::Sou => (S1, 9),
+            Suit::Honor => (X1, 7),
+        };
+        if (1..=count).contains(&number) {
+            Tile::from(first as u8 + number as u8 - 1)
+        } else {
+            None
+        }
+    }
+
     pub fn suit(self) -> Suit {
         if M1 <= self && self <= M9 {
             Suit::Man
@@ -156,6 +174,7 @@ impl Iterator for TileIterator {
     }
 }
 
+#[derive(Clone)]
 pub struct TileSet([isize; NUM_TILES]);
 
 impl Default for TileSet {
@@ -164,6 +183,28 @@ impl Default for TileSet {
     }
 }
 
+// Serialized as a list of counts (serde doesn't derive arrays that long)
+impl Serialize for TileSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0[..].serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TileSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let counts = Vec::<isize>::deserialize(deserializer)?;
+        if counts.len() != NUM_TILES {
+            return Err(de::Error::invalid_length(
+                counts.len(),
+                &"a count for each tile",
+            ));
+        }
+        let mut result = Self::new();
+        result.0.copy_from_slice(&counts);
+        Ok(result)
+    }
+}
+
 impl TileSet {
     pub fn new() -> Self {
         Self::default()
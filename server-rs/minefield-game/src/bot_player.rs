@@ -0,0 +1,186 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use minefield_core::bot::{Bot, BotMove, MoveKind, Thought};
+use minefield_core::rules::Ruleset;
+use minefield_core::seed::Seeder;
+use minefield_core::strategy::{self, DEFAULT_STRATEGY};
+
+use crate::protocol::{MoveType, Msg};
+
+pub const BOT_NICK: &str = "Bot";
+
+// A bot sitting in a room instead of a connected user. It receives the same
+// messages a user would, and makes its moves on beats. Choosing a move can
+// take a while, so it's handed out as a `BotTask`, and the move is made once
+// the result comes back.
+#[derive(Serialize, Deserialize)]
+pub struct BotPlayer {
+    you: usize,
+    #[serde(default = "default_strategy_name")]
+    strategy: String,
+    #[serde(default = "Seeder::from_entropy")]
+    seeder: Seeder,
+    #[serde(default)]
+    reproducible: bool,
+    bot: Option<Bot>,
+    // move to make, and beats left until making it
+    next_move: Option<(MoveType, usize)>,
+    // task we're waiting for, if any (after a restore, the move is simply
+    // chosen again, and late results are ignored)
+    #[serde(skip)]
+    thinking: Option<usize>,
+    #[serde(default)]
+    last_task: usize,
+}
+
+// Choosing a bot's move, to be done away from the room (see
+// `Lobby::beat_deferred`).
+pub struct BotTask {
+    pub(crate) room_id: usize,
+    seat: usize,
+    id: usize,
+    thought: Thought,
+}
+
+pub struct BotResult {
+    pub(crate) room_id: usize,
+    pub(crate) seat: usize,
+    id: usize,
+    bot_move: BotMove,
+}
+
+impl BotTask {
+    pub fn run(self) -> BotResult {
+        BotResult {
+            room_id: self.room_id,
+            seat: self.seat,
+            id: self.id,
+            bot_move: self.thought.run(),
+        }
+    }
+}
+
+impl BotPlayer {
+    pub fn new(you: usize, strategy: &str, seed: u64) -> Self {
+        BotPlayer {
+            you,
+            strategy: strategy.to_owned(),
+            seeder: Seeder::new(seed),
+            reproducible: false,
+            bot: None,
+            next_move: None,
+            thinking: None,
+            last_task: 0,
+        }
+    }
+
+    // No time limits on thinking, so that the moves depend only on the seed.
+    pub fn set_reproducible(&mut self, reproducible: bool) {
+        self.reproducible = reproducible;
+    }
+
+    // Seed of the bot playing the current hand
+    pub fn bot_seed(&self) -> Option<u64> {
+        self.bot.as_ref().map(Bot::seed)
+    }
+
+    // Returns a reply to send right away, if any.
+    pub fn on_message(&mut self, msg: &Msg, rules: &Ruleset) -> Option<Msg> {
+        match msg {
+            Msg::Room { you, .. } => self.you = *you,
+            Msg::PhaseOne {
+                tiles,
+                dora_ind,
+                you,
+                east,
+            } => {
+                self.you = *you;
+                let strategy =
+                    strategy::by_name(&self.strategy).unwrap_or_else(strategy::default_strategy);
+                let mut bot = Bot::with_strategy(
+                    tiles,
+                    *dora_ind,
+                    rules.player_wind(you == east),
+                    rules,
+                    strategy,
+                    self.seeder.next_seed(),
+                );
+                bot.set_reproducible(self.reproducible);
+                self.bot = Some(bot);
+                self.next_move = None;
+                self.thinking = None;
+            }
+            Msg::StartMove { move_type, .. } => {
+                // Even the hand is chosen on the next beat, so that the
+                // search doesn't hold up the other player's move.
+                let delay = match move_type {
+                    MoveType::Hand => 0,
+                    MoveType::Discard => self.seeder.rng().gen_range(1, 4),
+                };
+                self.next_move = Some((*move_type, delay));
+            }
+            Msg::Discarded { player, tile } if *player != self.you => {
+                if let Some(ref mut bot) = self.bot {
+                    bot.opponent_discard(*tile);
+                }
+            }
+            Msg::Ron { .. } | Msg::Draw | Msg::Abort { .. } => {
+                self.next_move = None;
+                self.thinking = None;
+            }
+            Msg::RematchOffered { player } if *player != self.you => {
+                return Some(Msg::RequestRematch);
+            }
+            _ => (),
+        }
+        None
+    }
+
+    // Returns a move to choose, if it's time to make one.
+    pub fn beat(&mut self) -> Option<BotTask> {
+        match self.next_move {
+            Some((_, 0)) if self.thinking.is_some() => None,
+            Some((move_type, 0)) => {
+                let bot = self.bot.as_mut()?;
+                let kind = match move_type {
+                    MoveType::Hand => MoveKind::Hand,
+                    MoveType::Discard => MoveKind::Discard,
+                };
+                self.last_task += 1;
+                self.thinking = Some(self.last_task);
+                Some(BotTask {
+                    room_id: 0,
+                    seat: self.you,
+                    id: self.last_task,
+                    thought: bot.think(kind),
+                })
+            }
+            Some((move_type, n)) => {
+                self.next_move = Some((move_type, n - 1));
+                None
+            }
+            None => None,
+        }
+    }
+
+    // Returns the move to make, unless it's too late for it (the hand ended
+    // while we were thinking).
+    pub fn finish(&mut self, result: BotResult) -> Option<Msg> {
+        if self.thinking != Some(result.id) {
+            return None;
+        }
+        self.thinking = None;
+        self.next_move = None;
+        let bot = self.bot.as_mut()?;
+        bot.apply(&result.bot_move);
+        Some(match result.bot_move {
+            BotMove::Hand(hand) => Msg::Hand { hand },
+            BotMove::Discard(tile) => Msg::Discard { tile },
+        })
+    }
+}
+
+fn default_strategy_name() -> String {
+    DEFAULT_STRATEGY.to_owned()
+}
@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+
+use crate::protocol::ChatMessage;
+
+pub const MAX_LENGTH: usize = 200;
+
+// At most LIMIT messages per user every WINDOW beats
+const LIMIT: usize = 5;
+const WINDOW: usize = 10;
+
+pub fn validate(message: &ChatMessage) -> Result<(), &'static str> {
+    match message {
+        ChatMessage::Text(text) if text.trim().is_empty() => Err("empty message"),
+        ChatMessage::Text(text) if text.chars().count() > MAX_LENGTH => Err("message too long"),
+        _ => Ok(()),
+    }
+}
+
+#[derive(Default)]
+pub struct RateLimiter {
+    sent: HashMap<usize, usize>,
+    beats: usize,
+}
+
+impl RateLimiter {
+    // Counts a message from the user, returns false if over the limit.
+    pub fn allow(&mut self, user_id: usize) -> bool {
+        let sent = self.sent.entry(user_id).or_insert(0);
+        if *sent >= LIMIT {
+            return false;
+        }
+        *sent += 1;
+        true
+    }
+
+    pub fn remove(&mut self, user_id: usize) {
+        self.sent.remove(&user_id);
+    }
+
+    pub fn beat(&mut self) {
+        self.beats += 1;
+        if self.beats >= WINDOW {
+            self.beats = 0;
+            self.sent.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&ChatMessage::Text("gg".to_owned())).is_ok());
+        assert!(validate(&ChatMessage::Text("  ".to_owned())).is_err());
+        assert!(validate(&ChatMessage::Text("x".repeat(MAX_LENGTH))).is_ok());
+        assert!(validate(&ChatMessage::Text("x".repeat(MAX_LENGTH + 1))).is_err());
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let mut limiter = RateLimiter::default();
+        for _ in 0..LIMIT {
+            assert!(limiter.allow(1));
+        }
+        assert!(!limiter.allow(1));
+        assert!(limiter.allow(2));
+        for _ in 0..WINDOW {
+            limiter.beat();
+        }
+        assert!(limiter.allow(1));
+    }
+}
@@ -1,48 +1,281 @@
 use std::collections::HashMap;
 
-use failure::Error;
-use rusqlite::{params, Connection};
+use failure::{format_err, Error};
+use log::{info, warn};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
 
-use crate::room::Room;
+use minefield_core::seed::Seeder;
+
+use crate::rating::{self, INITIAL_RATING};
+use crate::record::{GameRecord, GameResult};
+use crate::room::{hash_password, Room};
+
+// A finished game, as listed in history
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct GameSummary {
+    pub game_id: usize,
+    pub date: String,
+    pub nicks: [String; 2],
+    // "ron", "draw" or "abort"
+    pub result: String,
+    // For an abort, the player who didn't cause it
+    pub winner: Option<usize>,
+    pub points: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Default)]
+pub struct PlayerSummary {
+    pub nick: String,
+    pub games: usize,
+    pub wins: usize,
+    pub losses: usize,
+    pub draws: usize,
+    // Games aborted by the player, also counted as losses
+    pub aborts: usize,
+    pub points: isize,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Account {
+    pub player_id: usize,
+    pub nick: String,
+    pub rating: f64,
+}
 
 pub struct Database {
     conn: Connection,
 }
 
+// Schema migrations, applied in order. Never change a migration that has
+// been deployed, add a new one instead.
+const MIGRATIONS: &[&str] = &[
+    // 1: rooms
+    "
+    CREATE TABLE IF NOT EXISTS rooms (
+        create_date TIMESTAMP NOT NULL DEFAULT current_timestamp,
+        finished INTEGER NOT NULL,
+        data TEXT NOT NULL
+    );
+    ",
+    // 2: game history
+    "
+    CREATE TABLE IF NOT EXISTS games (
+        game_id INTEGER PRIMARY KEY,
+        room_id INTEGER NOT NULL,
+        create_date TIMESTAMP NOT NULL DEFAULT current_timestamp,
+        result TEXT NOT NULL,
+        winner INTEGER,
+        points INTEGER NOT NULL,
+        record TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS game_players (
+        game_id INTEGER NOT NULL REFERENCES games (game_id),
+        seat INTEGER NOT NULL,
+        nick TEXT NOT NULL,
+        player_key TEXT NOT NULL,
+        result TEXT NOT NULL,
+        points INTEGER NOT NULL,
+        PRIMARY KEY (game_id, seat)
+    );
+    CREATE INDEX IF NOT EXISTS game_players_nick ON game_players (nick);
+    CREATE TABLE IF NOT EXISTS game_yaku (
+        game_id INTEGER NOT NULL REFERENCES games (game_id),
+        seat INTEGER NOT NULL,
+        yaku TEXT NOT NULL,
+        fan INTEGER NOT NULL
+    );
+    ",
+    // 3: player accounts
+    "
+    CREATE TABLE players (
+        player_id INTEGER PRIMARY KEY,
+        create_date TIMESTAMP NOT NULL DEFAULT current_timestamp,
+        nick TEXT NOT NULL UNIQUE,
+        token TEXT NOT NULL UNIQUE,
+        rating REAL NOT NULL
+    );
+    ALTER TABLE game_players ADD COLUMN player_id INTEGER REFERENCES players (player_id);
+    ",
+    // 4: lobby seeder, so that a seeded server continues after a restart
+    "
+    CREATE TABLE lobby_state (
+        seeder TEXT NOT NULL
+    );
+    ",
+    // 5: drop player keys from history, they let anyone rejoin the room
+    "
+    CREATE TABLE game_players_new (
+        game_id INTEGER NOT NULL REFERENCES games (game_id),
+        seat INTEGER NOT NULL,
+        nick TEXT NOT NULL,
+        result TEXT NOT NULL,
+        points INTEGER NOT NULL,
+        player_id INTEGER REFERENCES players (player_id),
+        PRIMARY KEY (game_id, seat)
+    );
+    INSERT INTO game_players_new (game_id, seat, nick, result, points, player_id)
+        SELECT game_id, seat, nick, result, points, player_id FROM game_players;
+    DROP TABLE game_players;
+    ALTER TABLE game_players_new RENAME TO game_players;
+    CREATE INDEX game_players_nick ON game_players (nick);
+    ",
+    // 6: hash player tokens (in Rust, see HASHED_TOKENS)
+    "",
+];
+
+// Migration after which tokens are stored hashed. SQLite cannot hash them,
+// so we do it after running the migration.
+const HASHED_TOKENS: usize = 6;
+
+// Version of the serialized room, stored together with it. Bump it when
+// a change to Room or Game can't be handled by serde defaults, and add an
+// upgrade to ROOM_UPGRADES.
+pub const ROOM_VERSION: usize = 2;
+
+// Upgrades room JSON from version i to i + 1.
+const ROOM_UPGRADES: &[fn(&mut Value)] = &[
+    // 0 -> 1: no changes, only the envelope was added
+    |_| (),
+    // 1 -> 2: private room passwords are hashed
+    |room| {
+        let room_key = room["room_key"].as_str().unwrap_or_default().to_owned();
+        if let Some(private) = room["private"].as_object_mut() {
+            if let Some(Value::String(password)) = private.remove("password") {
+                let password_hash = hash_password(&room_key, &password);
+                private.insert("password_hash".to_owned(), Value::String(password_hash));
+            }
+        }
+    },
+];
+
+#[derive(Serialize)]
+struct RoomEnvelope<'a> {
+    version: usize,
+    room: &'a Room,
+}
+
+// Tokens are stored hashed, so that the database alone doesn't let anyone log
+// in. They are random and long, so a plain hash is enough.
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+fn serialize_room(room: &Room) -> Result<String, Error> {
+    Ok(serde_json::to_string(&RoomEnvelope {
+        version: ROOM_VERSION,
+        room,
+    })?)
+}
+
+fn deserialize_room(data: &str) -> Result<Room, Error> {
+    let mut value: Value = serde_json::from_str(data)?;
+    // Rooms saved before the envelope was added are version 0.
+    let (version, mut room) = match value.get("version").and_then(Value::as_u64) {
+        Some(version) => (version as usize, value["room"].take()),
+        None => (0, value),
+    };
+    if version > ROOM_VERSION {
+        return Err(format_err!("unknown room version: {}", version));
+    }
+    for upgrade in ROOM_UPGRADES[version..].iter() {
+        upgrade(&mut room);
+    }
+    Ok(serde_json::from_value(room)?)
+}
+
 impl Database {
     pub fn open(path: &str) -> Result<Self, Error> {
-        let conn = Connection::open(path)?;
+        let mut conn = Connection::open(path)?;
+        Self::migrate(&mut conn)?;
+        Ok(Database { conn })
+    }
+
+    fn migrate(conn: &mut Connection) -> Result<(), Error> {
         conn.execute(
-            "
-            CREATE TABLE IF NOT EXISTS rooms (
-                create_date TIMESTAMP NOT NULL DEFAULT current_timestamp,
-                finished INTEGER NOT NULL,
-                data TEXT NOT NULL
-            )
-            ",
+            "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)",
             params![],
         )?;
+        let version: Option<isize> = conn
+            .query_row("SELECT version FROM schema_version", params![], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        let version = version.unwrap_or(0) as usize;
+        if version > MIGRATIONS.len() {
+            return Err(format_err!("unknown schema version: {}", version));
+        }
 
-        Ok(Database { conn })
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            info!("applying migration {}", i + 1);
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            if i + 1 == HASHED_TOKENS {
+                Self::hash_tokens(&tx)?;
+            }
+            tx.execute("DELETE FROM schema_version", params![])?;
+            tx.execute(
+                "INSERT INTO schema_version (version) VALUES (?1)",
+                params![(i + 1) as isize],
+            )?;
+            tx.commit()?;
+        }
+        Ok(())
     }
 
+    fn hash_tokens(conn: &Connection) -> Result<(), Error> {
+        let mut stmt = conn.prepare("SELECT player_id, token FROM players")?;
+        let tokens = stmt
+            .query_map(params![], |row| {
+                Ok((row.get::<_, isize>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        for (player_id, token) in tokens {
+            conn.execute(
+                "UPDATE players SET token = ?1 WHERE player_id = ?2",
+                params![hash_token(&token), player_id],
+            )?;
+        }
+        Ok(())
+    }
+
+    // Loads unfinished rooms. Rooms that cannot be loaded anymore are
+    // marked as finished.
     pub fn load_rooms(&mut self) -> Result<HashMap<usize, Room>, Error> {
         let mut rooms = HashMap::new();
+        let mut abandoned = vec![];
 
         let mut stmt = self
             .conn
             .prepare("SELECT rowid, data FROM rooms WHERE NOT finished")?;
         for mapped_row in stmt.query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))? {
             let (room_id, room_data): (isize, String) = mapped_row?;
-            let room = serde_json::from_str(&room_data)?;
-            rooms.insert(room_id as usize, room);
+            match deserialize_room(&room_data) {
+                Ok(room) => {
+                    rooms.insert(room_id as usize, room);
+                }
+                Err(err) => {
+                    warn!("abandoning room {}: {}", room_id, err);
+                    abandoned.push(room_id);
+                }
+            }
+        }
+        drop(stmt);
+
+        for room_id in abandoned.into_iter() {
+            self.conn.execute(
+                "UPDATE rooms SET finished = 1 WHERE rowid = ?1",
+                params![room_id],
+            )?;
         }
 
         Ok(rooms)
     }
 
     pub fn new_room(&mut self, room: &Room) -> Result<usize, Error> {
-        let room_data = serde_json::to_string(room)?;
+        let room_data = serialize_room(room)?;
         self.conn.execute(
             "INSERT INTO rooms (finished, data) VALUES (?1, ?2)",
             params![room.finished(), room_data],
@@ -54,7 +287,7 @@ impl Database {
     }
 
     pub fn save_room(&mut self, room_id: usize, room: &Room) -> Result<(), Error> {
-        let room_data = serde_json::to_string(room)?;
+        let room_data = serialize_room(room)?;
         self.conn.execute(
             "UPDATE rooms SET finished = ?1, data = ?2 WHERE rowid = ?3",
             params![room.finished(), room_data, room_id as isize],
@@ -69,11 +302,274 @@ impl Database {
         )?;
         Ok(())
     }
+
+    pub fn load_seeder(&self) -> Result<Option<Seeder>, Error> {
+        let data: Option<String> = self
+            .conn
+            .query_row("SELECT seeder FROM lobby_state", params![], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        match data {
+            Some(data) => Ok(Some(serde_json::from_str(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn save_seeder(&mut self, seeder: &Seeder) -> Result<(), Error> {
+        let data = serde_json::to_string(seeder)?;
+        let tx = self.conn.transaction()?;
+        tx.execute("DELETE FROM lobby_state", params![])?;
+        tx.execute(
+            "INSERT INTO lobby_state (seeder) VALUES (?1)",
+            params![data],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    pub fn save_game(
+        &mut self,
+        room_id: usize,
+        record: &GameRecord,
+        player_ids: [Option<usize>; 2],
+    ) -> Result<usize, Error> {
+        let (result, winner, points) = match record.result {
+            Some(GameResult::Ron { player, points, .. }) => ("ron", Some(player), points),
+            Some(GameResult::Draw) => ("draw", None, 0),
+            // Lost by the culprit, same as for the rating
+            Some(GameResult::Abort { culprit, .. }) => ("abort", Some(1 - culprit), 0),
+            None => ("abort", None, 0),
+        };
+
+        let tx = self.conn.transaction()?;
+        tx.execute(
+            "INSERT INTO games (room_id, result, winner, points, record)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                room_id as isize,
+                result,
+                winner.map(|i| i as isize),
+                points as isize,
+                serde_json::to_string(record)?
+            ],
+        )?;
+        let game_id = tx.last_insert_rowid();
+
+        for (i, player_id) in player_ids.iter().enumerate() {
+            let (player_result, player_points) = match winner {
+                Some(w) if w == i => ("win", points as isize),
+                Some(_) => ("loss", -(points as isize)),
+                None => (result, 0),
+            };
+            tx.execute(
+                "INSERT INTO game_players
+                    (game_id, seat, nick, result, points, player_id)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![
+                    game_id,
+                    i as isize,
+                    record.players[i].nick,
+                    player_result,
+                    player_points,
+                    player_id.map(|id| id as isize)
+                ],
+            )?;
+        }
+
+        if let Some(GameResult::Ron {
+            player, ref yaku, ..
+        }) = record.result
+        {
+            for y in yaku.iter() {
+                let name = serde_json::to_value(y)?;
+                tx.execute(
+                    "INSERT INTO game_yaku (game_id, seat, yaku, fan) VALUES (?1, ?2, ?3, ?4)",
+                    params![game_id, player as isize, name.as_str(), y.fan() as isize],
+                )?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(game_id as usize)
+    }
+
+    pub fn new_account(&mut self, nick: &str, token: &str) -> Result<Option<Account>, Error> {
+        let inserted = self.conn.execute(
+            "INSERT OR IGNORE INTO players (nick, token, rating) VALUES (?1, ?2, ?3)",
+            params![nick, hash_token(token), INITIAL_RATING],
+        )?;
+        if inserted == 0 {
+            // nick already taken
+            return Ok(None);
+        }
+        Ok(Some(Account {
+            player_id: self.conn.last_insert_rowid() as usize,
+            nick: nick.to_owned(),
+            rating: INITIAL_RATING,
+        }))
+    }
+
+    pub fn nick_registered(&self, nick: &str) -> Result<bool, Error> {
+        let found = self
+            .conn
+            .query_row(
+                "SELECT 1 FROM players WHERE nick = ?1",
+                params![nick],
+                |_| Ok(()),
+            )
+            .optional()?;
+        Ok(found.is_some())
+    }
+
+    pub fn find_account(&self, token: &str) -> Result<Option<Account>, Error> {
+        let account = self
+            .conn
+            .query_row(
+                "SELECT player_id, nick, rating FROM players WHERE token = ?1",
+                params![hash_token(token)],
+                |row| {
+                    let player_id: isize = row.get(0)?;
+                    Ok(Account {
+                        player_id: player_id as usize,
+                        nick: row.get(1)?,
+                        rating: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(account)
+    }
+
+    // Updates ratings after a game, returns the new ones.
+    pub fn update_ratings(
+        &mut self,
+        player_ids: [usize; 2],
+        score: f64,
+    ) -> Result<[f64; 2], Error> {
+        let tx = self.conn.transaction()?;
+        let mut ratings = [0.0; 2];
+        for i in 0..2 {
+            ratings[i] = tx.query_row(
+                "SELECT rating FROM players WHERE player_id = ?1",
+                params![player_ids[i] as isize],
+                |row| row.get(0),
+            )?;
+        }
+        let ratings = rating::update(ratings, score);
+        for i in 0..2 {
+            tx.execute(
+                "UPDATE players SET rating = ?1 WHERE player_id = ?2",
+                params![ratings[i], player_ids[i] as isize],
+            )?;
+        }
+        tx.commit()?;
+        Ok(ratings)
+    }
+
+    pub fn recent_games(&self, limit: usize) -> Result<Vec<GameSummary>, Error> {
+        self.query_games("TRUE", params![limit as isize])
+    }
+
+    pub fn games_by_nick(&self, nick: &str, limit: usize) -> Result<Vec<GameSummary>, Error> {
+        self.query_games(
+            "game_id IN (SELECT game_id FROM game_players WHERE nick = ?2)",
+            params![limit as isize, nick],
+        )
+    }
+
+    fn query_games(
+        &self,
+        condition: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> Result<Vec<GameSummary>, Error> {
+        let mut stmt = self.conn.prepare(&format!(
+            "SELECT game_id, create_date, result, winner, points,
+                (SELECT nick FROM game_players p WHERE p.game_id = g.game_id AND seat = 0),
+                (SELECT nick FROM game_players p WHERE p.game_id = g.game_id AND seat = 1)
+             FROM games g
+             WHERE {}
+             ORDER BY game_id DESC
+             LIMIT ?1",
+            condition
+        ))?;
+        let games = stmt
+            .query_map(params, Self::game_summary)?
+            .collect::<Result<_, _>>()?;
+        Ok(games)
+    }
+
+    fn game_summary(row: &Row) -> rusqlite::Result<GameSummary> {
+        let game_id: isize = row.get(0)?;
+        let winner: Option<isize> = row.get(3)?;
+        let points: isize = row.get(4)?;
+        Ok(GameSummary {
+            game_id: game_id as usize,
+            date: row.get(1)?,
+            result: row.get(2)?,
+            winner: winner.map(|w| w as usize),
+            points: points as usize,
+            nicks: [row.get(5)?, row.get(6)?],
+        })
+    }
+
+    pub fn game_record(&self, game_id: usize) -> Result<Option<GameRecord>, Error> {
+        let data: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT record FROM games WHERE game_id = ?1",
+                params![game_id as isize],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match data {
+            Some(data) => Ok(Some(serde_json::from_str(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn player_summary(&self, nick: &str) -> Result<PlayerSummary, Error> {
+        let mut summary = PlayerSummary {
+            nick: nick.to_owned(),
+            ..PlayerSummary::default()
+        };
+        let mut stmt = self.conn.prepare(
+            "SELECT p.result, g.result, COUNT(*), SUM(p.points)
+             FROM game_players p JOIN games g ON p.game_id = g.game_id
+             WHERE p.nick = ?1 GROUP BY p.result, g.result",
+        )?;
+        let mut rows = stmt.query(params![nick])?;
+        while let Some(row) = rows.next()? {
+            let result: String = row.get(0)?;
+            let game_result: String = row.get(1)?;
+            let count: isize = row.get(2)?;
+            let points: isize = row.get(3)?;
+            let count = count as usize;
+            match (result.as_str(), game_result.as_str()) {
+                ("win", _) => summary.wins += count,
+                ("loss", "abort") => {
+                    summary.losses += count;
+                    summary.aborts += count;
+                }
+                ("loss", _) => summary.losses += count,
+                ("draw", _) => summary.draws += count,
+                // Unfinished, or from before the culprit was recorded
+                _ => summary.aborts += count,
+            }
+            summary.games += count;
+            summary.points += points;
+        }
+        Ok(summary)
+    }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::record::PlayerRecord;
+    use minefield_core::rules::Ruleset;
+    use minefield_core::tiles::Tile;
+    use minefield_core::yaku::Yaku;
 
     #[test]
     fn test() {
@@ -92,4 +588,198 @@ mod test {
         let rooms = db.load_rooms().unwrap();
         assert_eq!(rooms.len(), 0);
     }
+
+    fn record(nicks: [&str; 2], result: Option<GameResult>) -> GameRecord {
+        let player = |nick: &str| PlayerRecord {
+            nick: nick.to_owned(),
+            tiles: vec![],
+            hand: vec![],
+            discards: vec![],
+            bot_seed: None,
+        };
+        GameRecord {
+            rules: Ruleset::default(),
+            east: 0,
+            dora_ind: Tile::M1,
+            uradora_ind: Tile::M2,
+            seed: None,
+            players: [player(nicks[0]), player(nicks[1])],
+            result,
+        }
+    }
+
+    #[test]
+    fn test_history() {
+        let mut db = Database::open(":memory:").unwrap();
+        let ron = GameResult::Ron {
+            player: 1,
+            hand: vec![],
+            tile: Tile::M1,
+            yaku: vec![Yaku::Riichi, Yaku::Chinitsu],
+            dora: 0,
+            uradora_ind: Tile::M2,
+            limit: 3,
+            points: 16000,
+            details: None,
+        };
+        let game_id = db
+            .save_game(1, &record(["Akagi", "Washizu"], Some(ron)), [None, None])
+            .unwrap();
+        db.save_game(
+            2,
+            &record(["Washizu", "Akagi"], Some(GameResult::Draw)),
+            [None, None],
+        )
+        .unwrap();
+        db.save_game(3, &record(["Ichikawa", "Washizu"], None), [None, None])
+            .unwrap();
+
+        let games = db.recent_games(2).unwrap();
+        assert_eq!(games.len(), 2);
+        assert_eq!(
+            games[0].nicks,
+            ["Ichikawa".to_owned(), "Washizu".to_owned()]
+        );
+        assert_eq!(games[0].result, "abort");
+        assert_eq!(games[1].result, "draw");
+
+        let games = db.games_by_nick("Akagi", 10).unwrap();
+        assert_eq!(games.len(), 2);
+        assert_eq!(games[1].game_id, game_id);
+        assert_eq!(games[1].winner, Some(1));
+        assert_eq!(games[1].points, 16000);
+
+        assert_eq!(
+            db.player_summary("Washizu").unwrap(),
+            PlayerSummary {
+                nick: "Washizu".to_owned(),
+                games: 3,
+                wins: 1,
+                losses: 0,
+                draws: 1,
+                aborts: 1,
+                points: 16000,
+            }
+        );
+        assert_eq!(db.player_summary("Akagi").unwrap().points, -16000);
+        assert_eq!(db.player_summary("nobody").unwrap().games, 0);
+
+        // lost by the culprit
+        let abort = GameResult::Abort {
+            culprit: 0,
+            description: "timeout".to_owned(),
+        };
+        db.save_game(4, &record(["Akagi", "Ichikawa"], Some(abort)), [None, None])
+            .unwrap();
+        assert_eq!(db.recent_games(1).unwrap()[0].winner, Some(1));
+        let summary = db.player_summary("Akagi").unwrap();
+        assert_eq!((summary.wins, summary.losses, summary.aborts), (0, 2, 1));
+        let summary = db.player_summary("Ichikawa").unwrap();
+        assert_eq!((summary.wins, summary.losses, summary.aborts), (1, 0, 1));
+
+        let record = db.game_record(game_id).unwrap().unwrap();
+        assert_eq!(record.players[0].nick, "Akagi");
+        assert_eq!(db.game_record(100).unwrap(), None);
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // a database from before migrations
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        Database::migrate(&mut conn).unwrap();
+        Database::migrate(&mut conn).unwrap();
+
+        let version: isize = conn
+            .query_row("SELECT version FROM schema_version", params![], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(version as usize, MIGRATIONS.len());
+        // no rejoin keys in history
+        assert!(conn.prepare("SELECT player_key FROM game_players").is_err());
+
+        conn.execute("UPDATE schema_version SET version = 100", params![])
+            .unwrap();
+        assert!(Database::migrate(&mut conn).is_err());
+    }
+
+    #[test]
+    fn test_hashed_tokens() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        // a database with tokens in plain text
+        for migration in MIGRATIONS[..HASHED_TOKENS - 1].iter() {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.execute_batch(
+            "CREATE TABLE schema_version (version INTEGER NOT NULL);
+             INSERT INTO schema_version (version) VALUES (5);
+             INSERT INTO players (nick, token, rating) VALUES ('Akagi', 'secret', 1500);",
+        )
+        .unwrap();
+        Database::migrate(&mut conn).unwrap();
+
+        let stored: String = conn
+            .query_row("SELECT token FROM players", params![], |row| row.get(0))
+            .unwrap();
+        assert_ne!(stored, "secret");
+        let mut db = Database { conn };
+        assert_eq!(db.find_account("secret").unwrap().unwrap().nick, "Akagi");
+        assert_eq!(db.find_account(&stored).unwrap(), None);
+
+        let account = db.new_account("Washizu", "token").unwrap().unwrap();
+        assert_eq!(db.find_account("token").unwrap(), Some(account));
+    }
+
+    #[test]
+    fn test_room_versions() {
+        let mut db = Database::open(":memory:").unwrap();
+        let room = Room::new(10, "xxx".to_owned());
+        let old = serde_json::to_string(&room).unwrap();
+        let current = serialize_room(&room).unwrap();
+        let future = current.replacen(
+            &format!(r#""version":{}"#, ROOM_VERSION),
+            r#""version":1000"#,
+            1,
+        );
+        for data in [old, current, future, "{}".to_owned()].iter() {
+            db.conn
+                .execute(
+                    "INSERT INTO rooms (finished, data) VALUES (0, ?1)",
+                    params![data],
+                )
+                .unwrap();
+        }
+
+        let rooms = db.load_rooms().unwrap();
+        let mut room_ids: Vec<usize> = rooms.keys().copied().collect();
+        room_ids.sort_unstable();
+        assert_eq!(room_ids, vec![1, 2]);
+
+        // the others are abandoned
+        assert_eq!(db.load_rooms().unwrap().len(), 2);
+        let finished: isize = db
+            .conn
+            .query_row(
+                "SELECT COUNT(*) FROM rooms WHERE finished",
+                params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(finished, 2);
+    }
+
+    #[test]
+    fn test_room_password_upgrade() {
+        let room = Room::new(10, "xxx".to_owned());
+        let mut value: Value = serde_json::to_value(&room).unwrap();
+        value["private"] = serde_json::json!({"password": "secret"});
+        let data = serde_json::json!({"version": 1, "room": value}).to_string();
+
+        let room = deserialize_room(&data).unwrap();
+        assert!(room.private());
+        assert!(room.check_access("yyy", Some("guess")).is_err());
+        assert!(room.check_access("yyy", Some("secret")).is_ok());
+        assert!(!serialize_room(&room).unwrap().contains("secret"));
+    }
 }
@@ -1,22 +1,22 @@
+use rand::rngs::StdRng;
 use rand::seq::SliceRandom;
+use rand::SeedableRng;
 use serde::{Deserialize, Serialize};
 
+use minefield_core::rules::Ruleset;
 use minefield_core::score::Score;
 use minefield_core::search::{find_all_waits, search};
 use minefield_core::tiles::Tile;
 use minefield_core::yaku;
 use yaku::Yaku;
 
-use crate::protocol::{MoveType, Msg};
-
-const PLAYER_TILES: usize = 34;
-const DISCARDS: usize = 17;
-const DISCARD_TIME_LIMIT: usize = 15;
-const HAND_TIME_LIMIT: usize = 3 * 60;
-const EXTRA_TIME: usize = 10;
+use crate::protocol::{MoveType, Msg, ScoreDetails};
+use crate::record::{GameRecord, GameResult, PlayerRecord};
 
 #[derive(Serialize, Deserialize)]
 pub struct Game {
+    #[serde(default)]
+    rules: Ruleset,
     east: usize,
     players: [Player; 2],
     dora_ind: Tile,
@@ -24,6 +24,12 @@ pub struct Game {
     pub finished: bool,
     time: usize,
     messages: Vec<(usize, Msg)>,
+    // Ron, Draw or Abort
+    #[serde(default)]
+    result: Option<Msg>,
+    // what the tiles were dealt from, if known
+    #[serde(default)]
+    seed: Option<u64>,
 }
 
 pub enum GameError {
@@ -31,25 +37,42 @@ pub enum GameError {
 }
 
 impl Game {
-    pub fn new(rng: &mut impl rand::Rng) -> Self {
+    pub fn new(rng: &mut impl rand::Rng, rules: Ruleset) -> Self {
+        let east = rng.gen_range(0, 2);
+        Self::with_east(rng, east, rules)
+    }
+
+    pub fn with_east(rng: &mut impl rand::Rng, east: usize, rules: Ruleset) -> Self {
         let mut all_tiles = all_tiles();
         all_tiles.shuffle(rng);
 
-        let east = rng.gen_range(0, 2);
+        Self::fixed(&all_tiles, east, rules)
+    }
 
-        Self::fixed(&all_tiles, east)
+    // Deals with an RNG seeded with `seed`, which is then recorded. East
+    // is chosen randomly too, unless given.
+    pub fn from_seed(seed: u64, east: Option<usize>, rules: Ruleset) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut game = match east {
+            Some(east) => Self::with_east(&mut rng, east, rules),
+            None => Self::new(&mut rng, rules),
+        };
+        game.seed = Some(seed);
+        game
     }
 
-    pub fn fixed(all_tiles: &[Tile], east: usize) -> Self {
+    pub fn fixed(all_tiles: &[Tile], east: usize, rules: Ruleset) -> Self {
+        let player_tiles = rules.player_tiles;
         let players = [
-            Player::new(&all_tiles[0..PLAYER_TILES], east == 0),
-            Player::new(&all_tiles[PLAYER_TILES..PLAYER_TILES * 2], east == 1),
+            Player::new(&all_tiles[0..player_tiles], east == 0),
+            Player::new(&all_tiles[player_tiles..player_tiles * 2], east == 1),
         ];
 
-        let dora_ind = all_tiles[PLAYER_TILES * 2];
-        let uradora_ind = all_tiles[PLAYER_TILES * 2 + 1];
+        let dora_ind = all_tiles[player_tiles * 2];
+        let uradora_ind = all_tiles[player_tiles * 2 + 1];
 
         Game {
+            rules,
             east,
             players,
             dora_ind,
@@ -57,9 +80,15 @@ impl Game {
             finished: false,
             time: 0,
             messages: vec![],
+            result: None,
+            seed: None,
         }
     }
 
+    pub fn east(&self) -> usize {
+        self.east
+    }
+
     pub fn on_start(&mut self) {
         for i in 0..2 {
             self.send(
@@ -124,10 +153,10 @@ impl Game {
     fn start_move(&mut self, i: usize, move_type: MoveType) {
         assert!(self.players[i].deadline.is_none());
         let time_limit = match move_type {
-            MoveType::Hand => HAND_TIME_LIMIT,
-            MoveType::Discard => DISCARD_TIME_LIMIT,
+            MoveType::Hand => self.rules.hand_time_limit,
+            MoveType::Discard => self.rules.discard_time_limit,
         };
-        self.players[i].deadline = Some(self.time + time_limit + EXTRA_TIME);
+        self.players[i].deadline = Some(self.time + time_limit + self.rules.extra_time);
         self.send(
             i,
             Msg::StartMove {
@@ -141,7 +170,7 @@ impl Game {
         assert!(!self.finished);
         if self.players[i].deadline.is_some() {
             let move_type = self.players[i].current_move_type();
-            let time_limit = self.players[i].current_time_limit(self.time);
+            let time_limit = self.players[i].current_time_limit(self.time, &self.rules);
             Some(Msg::StartMove {
                 move_type,
                 time_limit,
@@ -151,6 +180,41 @@ impl Game {
         }
     }
 
+    pub fn snapshot(&self, i: usize) -> Msg {
+        Msg::Snapshot {
+            you: i,
+            east: self.east,
+            dora_ind: self.dora_ind,
+            tiles: self.players[i].tiles.clone(),
+            hand: self.players[i].hand.clone(),
+            discards: [
+                self.players[0].discards.clone(),
+                self.players[1].discards.clone(),
+            ],
+            phase_two: self.is_phase2(),
+            result: self.result.clone().map(Box::new),
+        }
+    }
+
+    pub fn record(&self, nicks: &[String; 2]) -> GameRecord {
+        let players = [0, 1].map(|i| PlayerRecord {
+            nick: nicks[i].clone(),
+            tiles: self.players[i].initial_tiles.clone(),
+            hand: self.players[i].hand.clone(),
+            discards: self.players[i].discards.clone(),
+            bot_seed: None,
+        });
+        GameRecord {
+            rules: self.rules.clone(),
+            east: self.east,
+            dora_ind: self.dora_ind,
+            uradora_ind: self.uradora_ind,
+            seed: self.seed,
+            players,
+            result: self.result.as_ref().and_then(GameResult::from_msg),
+        }
+    }
+
     fn end_move(&mut self, i: usize) {
         assert!(self.players[i].deadline.is_some());
         self.players[i].deadline = None;
@@ -186,20 +250,24 @@ impl Game {
     }
 
     fn abort(&mut self, culprit: usize, description: &str) {
-        self.finished = true;
-
-        self.send_both(Msg::Abort {
+        self.finish(Msg::Abort {
             culprit,
             description: description.to_owned(),
         });
     }
 
+    fn finish(&mut self, result: Msg) {
+        self.finished = true;
+        self.send_both(result.clone());
+        self.result = Some(result);
+    }
+
     pub fn on_discard(&mut self, i: usize, tile: Tile) {
         if !self.is_phase2() {
             return self.abort(i, "discard too soon");
         }
 
-        if let Err(description) = self.players[i].discard(tile) {
+        if let Err(description) = self.players[i].discard(tile, &self.rules) {
             return self.abort(i, description);
         }
         self.end_move(i);
@@ -209,18 +277,14 @@ impl Game {
 
         // ron
         if let Some(msg) =
-            self.players[1 - i].check_ron(1 - i, tile, self.dora_ind, self.uradora_ind)
+            self.players[1 - i].check_ron(1 - i, tile, self.dora_ind, self.uradora_ind, &self.rules)
         {
-            self.send_both(msg);
-            self.finished = true;
-            return;
+            return self.finish(msg);
         }
 
         // draw
-        if self.players[0].finished() && self.players[1].finished() {
-            self.send_both(Msg::Draw);
-            self.finished = true;
-            return;
+        if self.players[0].finished(&self.rules) && self.players[1].finished(&self.rules) {
+            return self.finish(Msg::Draw);
         }
 
         // normal turn
@@ -238,6 +302,8 @@ fn all_tiles() -> Vec<Tile> {
 
 #[derive(Serialize, Deserialize)]
 struct Player {
+    #[serde(default)]
+    initial_tiles: Vec<Tile>,
     tiles: Vec<Tile>,
     is_east: bool,
     deadline: Option<usize>,
@@ -250,6 +316,7 @@ struct Player {
 impl Player {
     fn new(tiles: &[Tile], is_east: bool) -> Self {
         Player {
+            initial_tiles: tiles.to_vec(),
             tiles: tiles.to_vec(),
             is_east,
             deadline: None,
@@ -286,13 +353,13 @@ impl Player {
             .ok_or("tile not found in choices")
     }
 
-    fn discard(&mut self, tile: Tile) -> Result<(), &'static str> {
+    fn discard(&mut self, tile: Tile, rules: &Ruleset) -> Result<(), &'static str> {
         if !self.deadline.is_some() || self.hand.is_empty() {
             return Err("not expecting a discard");
         }
         let idx = self.find_choice(tile)?;
         self.tiles.remove(idx);
-        assert!(self.discards.len() < DISCARDS);
+        assert!(self.discards.len() < rules.discards);
         self.discards.push(tile);
         if self.waits.contains(&tile) {
             self.furiten = true;
@@ -300,8 +367,8 @@ impl Player {
         Ok(())
     }
 
-    fn finished(&self) -> bool {
-        self.discards.len() == DISCARDS
+    fn finished(&self, rules: &Ruleset) -> bool {
+        self.discards.len() == rules.discards
     }
 
     fn current_move_type(&self) -> MoveType {
@@ -313,11 +380,11 @@ impl Player {
         }
     }
 
-    fn current_time_limit(&self, time: usize) -> usize {
+    fn current_time_limit(&self, time: usize, rules: &Ruleset) -> usize {
         match self.deadline {
             Some(deadline) => {
-                if time + EXTRA_TIME < deadline {
-                    deadline - time - EXTRA_TIME
+                if time + rules.extra_time < deadline {
+                    deadline - time - rules.extra_time
                 } else {
                     0
                 }
@@ -333,6 +400,7 @@ impl Player {
         tile: Tile,
         dora_ind: Tile,
         uradora_ind: Tile,
+        rules: &Ruleset,
     ) -> Option<Msg> {
         if !self.waits.contains(&tile) {
             return None;
@@ -341,7 +409,7 @@ impl Player {
             return None;
         }
 
-        let player_wind = if self.is_east { Tile::X1 } else { Tile::X3 };
+        let player_wind = rules.player_wind(self.is_east);
         let dora = dora_ind.next_wrap();
         let uradora = uradora_ind.next_wrap();
         let turn = if self.is_east {
@@ -355,26 +423,30 @@ impl Player {
         let hands = search(&full_hand, tile);
         let scored_hands = hands.iter().filter_map(|hand| {
             let mut special = vec![Yaku::Riichi];
-            if turn == 0 {
+            if rules.ippatsu && turn == 0 {
                 special.push(Yaku::Ippatsu);
             }
-            if turn == DISCARDS - 1 {
+            if rules.hotei && turn == rules.discards - 1 {
                 special.push(Yaku::Hotei);
             }
             let mut score = Score::from_hand(hand, player_wind, &special);
 
-            // Check if mangan (with dora)
+            // Check if enough to win (with dora)
             score.add_dora(dora);
-            if score.limit() == 0 {
+            if score.limit(rules) < rules.min_limit {
                 return None;
             }
 
             // Return score (with dora and uradora)
-            score.add_dora(uradora);
+            if rules.uradora {
+                score.add_dora(uradora);
+            }
             Some(score)
         });
 
-        if let Some(score) = scored_hands.max_by_key(|score| (score.points(), score.fan())) {
+        if let Some(score) =
+            scored_hands.max_by_key(|score| (score.points(rules, self.is_east), score.fan()))
+        {
             Some(Msg::Ron {
                 player: i,
                 hand: full_hand,
@@ -382,8 +454,12 @@ impl Player {
                 yaku: score.yaku.clone(),
                 dora: score.dora_count,
                 uradora_ind,
-                limit: score.limit(),
-                points: score.points(),
+                limit: score.limit(rules),
+                points: score.points(rules, self.is_east),
+                details: Some(ScoreDetails {
+                    fu: score.fu_breakdown.clone(),
+                    yaku: score.yaku_fan(),
+                }),
             })
         } else {
             self.furiten = true;
@@ -395,10 +471,16 @@ impl Player {
 #[cfg(test)]
 mod test {
     use super::*;
+    use minefield_core::fu::fu;
+    use minefield_core::hand::Hand;
     use Tile::*;
 
+    fn rules() -> Ruleset {
+        Ruleset::default()
+    }
+
     fn game() -> Game {
-        Game::fixed(&all_tiles(), 0)
+        Game::fixed(&all_tiles(), 0, Ruleset::default())
     }
 
     fn assert_aborted(game: &mut Game, culprit: usize, description: &str) {
@@ -420,7 +502,7 @@ mod test {
                 (
                     0,
                     Msg::PhaseOne {
-                        tiles: all_tiles[0..PLAYER_TILES].to_vec(),
+                        tiles: all_tiles[0..rules().player_tiles].to_vec(),
                         dora_ind: M1,
                         you: 0,
                         east: 0
@@ -430,13 +512,13 @@ mod test {
                     0,
                     Msg::StartMove {
                         move_type: MoveType::Hand,
-                        time_limit: HAND_TIME_LIMIT
+                        time_limit: rules().hand_time_limit
                     }
                 ),
                 (
                     1,
                     Msg::PhaseOne {
-                        tiles: all_tiles[PLAYER_TILES..2 * PLAYER_TILES].to_vec(),
+                        tiles: all_tiles[rules().player_tiles..2 * rules().player_tiles].to_vec(),
                         dora_ind: M1,
                         you: 1,
                         east: 0
@@ -446,7 +528,7 @@ mod test {
                     1,
                     Msg::StartMove {
                         move_type: MoveType::Hand,
-                        time_limit: HAND_TIME_LIMIT
+                        time_limit: rules().hand_time_limit
                     }
                 )
             ]
@@ -454,7 +536,11 @@ mod test {
     }
 
     fn start_game(hand_0: &[Tile], hand_1: &[Tile]) -> Game {
-        let mut game = game();
+        start_game_with_rules(hand_0, hand_1, rules())
+    }
+
+    fn start_game_with_rules(hand_0: &[Tile], hand_1: &[Tile], rules: Ruleset) -> Game {
+        let mut game = Game::fixed(&all_tiles(), 0, rules);
         game.on_start();
         game.messages();
         game.on_message(
@@ -506,7 +592,7 @@ mod test {
                 player,
                 Msg::StartMove {
                     move_type: MoveType::Discard,
-                    time_limit: DISCARD_TIME_LIMIT
+                    time_limit: rules().discard_time_limit
                 }
             )]
         );
@@ -527,7 +613,28 @@ mod test {
             &[M1, M2, M3, M4, M5, M6, M7, M8, M9, P1, P2, P3, P4],
             &[M1, M2, M3, M4, M5, M6, M7, M8, M9, P1, P2, P3, P4],
         );
-        for _ in 0..DISCARDS {
+        for _ in 0..rules().discards {
+            for player in 0..2 {
+                let tile = game.players[player].tiles[0];
+                discard(&mut game, player, tile);
+            }
+        }
+        assert_eq!(game.messages(), vec![(0, Msg::Draw), (1, Msg::Draw)]);
+        assert_eq!(game.finished, true);
+    }
+
+    #[test]
+    fn test_draw_custom_discards() {
+        let rules = Ruleset {
+            discards: 5,
+            ..Ruleset::default()
+        };
+        let mut game = start_game_with_rules(
+            &[M1, M2, M3, M4, M5, M6, M7, M8, M9, P1, P2, P3, P4],
+            &[M1, M2, M3, M4, M5, M6, M7, M8, M9, P1, P2, P3, P4],
+            rules,
+        );
+        for _ in 0..5 {
             for player in 0..2 {
                 let tile = game.players[player].tiles[0];
                 discard(&mut game, player, tile);
@@ -562,11 +669,53 @@ mod test {
             dora: 0,
             uradora_ind: M2,
             tile: P1,
+            details: Some(ScoreDetails {
+                fu: fu(&Hand::Kokushi(P1, P1), X1),
+                yaku: vec![(Yaku::Kokushi, 13)],
+            }),
         };
         assert_eq!(game.messages(), vec![(0, ron.clone()), (1, ron)]);
         assert_eq!(game.finished, true);
     }
 
+    #[test]
+    fn test_record() {
+        let mut game = start_game(
+            &[M1, M9, P1, P9, S1, S9, X1, X2, X3, X4, X5, X6, X7],
+            &[M1, M2, M3, M4, M5, M6, P7, P8, P9, S1, S2, S3, S4],
+        );
+        discard(&mut game, 0, S4);
+        discard(&mut game, 1, P1);
+
+        let nicks = ["Akagi".to_owned(), "Washizu".to_owned()];
+        let record = game.record(&nicks);
+        assert_eq!(record.players[0].tiles, all_tiles()[0..34].to_vec());
+        assert_eq!(record.players[1].hand[0..3], [M1, M2, M3]);
+        assert_eq!(record.discards(), vec![(0, S4), (1, P1)]);
+        assert!(matches!(
+            record.result,
+            Some(GameResult::Ron {
+                player: 0,
+                points: 32000,
+                ..
+            })
+        ));
+        let json: GameRecord = serde_json::from_str(&record.to_json()).unwrap();
+        assert_eq!(json, record);
+
+        let tenhou = record.to_tenhou();
+        assert_eq!(tenhou["name"][1], "Washizu");
+        let log = &tenhou["log"][0];
+        assert_eq!(log[2], serde_json::json!([11]));
+        assert_eq!(log[5], serde_json::json!([34]));
+        assert_eq!(log[6], serde_json::json!([60]));
+        assert_eq!(log[8], serde_json::json!([21]));
+        assert_eq!(log[16][0], "和了");
+        assert_eq!(log[16][1], serde_json::json!([32000, -32000, 0, 0]));
+        assert_eq!(log[16][2][3], "役満32000点");
+        assert_eq!(log[16][2][4], "国士無双(役満)");
+    }
+
     #[test]
     fn test_furiten() {
         // P0: junk
@@ -628,7 +777,7 @@ mod test {
             },
         );
         game.messages();
-        for _ in 0..HAND_TIME_LIMIT + EXTRA_TIME {
+        for _ in 0..rules().hand_time_limit + rules().extra_time {
             game.beat();
         }
         assert_aborted(&mut game, 1, "time limit exceeded");
@@ -641,7 +790,7 @@ mod test {
             &[M1, M2, M3, M4, M5, M6, M7, M8, M9, P1, P2, P3, P4],
         );
         game.messages();
-        for _ in 0..DISCARD_TIME_LIMIT + EXTRA_TIME {
+        for _ in 0..rules().discard_time_limit + rules().extra_time {
             game.beat();
         }
         assert_aborted(&mut game, 0, "time limit exceeded");
@@ -655,11 +804,33 @@ mod test {
         player.deadline = Some(0);
         player.set_hand(&tiles).unwrap();
         player.discards.push(X3); // not ippatsu
-        let msg = player.check_ron(0, X2, X3, X3);
+        let msg = player.check_ron(0, X2, X3, X3, &rules());
         if let Some(Msg::Ron { yaku, .. }) = msg {
             assert_eq!(yaku, vec![Yaku::Riichi, Yaku::Ryanpeiko, Yaku::Honitsu]);
         } else {
             panic!("expecting Ron");
         }
     }
+
+    #[test]
+    fn test_ron_uradora() {
+        let tiles = vec![S1, S1, S2, S2, S3, S3, S5, S5, S6, S6, S7, S7, X2];
+        let check = |rules: &Ruleset| {
+            let mut player = Player::new(&tiles, false);
+            player.deadline = Some(0);
+            player.set_hand(&tiles).unwrap();
+            player.discards.push(X3);
+            match player.check_ron(0, X2, X3, X1, rules) {
+                Some(Msg::Ron { dora, .. }) => dora,
+                _ => panic!("expecting Ron"),
+            }
+        };
+
+        assert_eq!(check(&rules()), 2);
+        let rules = Ruleset {
+            uradora: false,
+            ..Ruleset::default()
+        };
+        assert_eq!(check(&rules), 0);
+    }
 }
@@ -0,0 +1,247 @@
+use serde::{Deserialize, Serialize};
+
+use minefield_core::rules::Ruleset;
+
+use crate::protocol::Msg;
+
+// A series of hands between the same two players. The match only keeps
+// the score; the room starts a new Game for every hand.
+#[derive(Serialize, Deserialize)]
+pub struct Match {
+    // hands played so far (repeated hands not counted)
+    hand: usize,
+    east: usize,
+    honba: usize,
+    points: [isize; 2],
+    // beats left until the next hand
+    next_hand: Option<usize>,
+    // player who caused an abort, and so lost the match
+    #[serde(default)]
+    forfeit: Option<usize>,
+    pub finished: bool,
+}
+
+impl Match {
+    pub fn new(east: usize) -> Self {
+        Match {
+            hand: 0,
+            east,
+            honba: 0,
+            points: [0, 0],
+            next_hand: None,
+            forfeit: None,
+            finished: false,
+        }
+    }
+
+    pub fn east(&self) -> usize {
+        self.east
+    }
+
+    pub fn status_msg(&self, rules: &Ruleset) -> Msg {
+        Msg::MatchStatus {
+            hand: self.hand,
+            hands: rules.hands,
+            honba: self.honba,
+            points: self.points,
+        }
+    }
+
+    // Record the end of a hand. Returns messages for both players.
+    pub fn on_result(&mut self, result: &Msg, rules: &Ruleset) -> Vec<Msg> {
+        assert!(!self.finished && self.next_hand.is_none());
+        match result {
+            Msg::Ron { player, points, .. } => {
+                let points = (points + self.honba * rules.honba_points) as isize;
+                self.points[*player] += points;
+                self.points[1 - *player] -= points;
+                self.honba = 0;
+                self.advance();
+            }
+            Msg::Draw => {
+                self.honba += 1;
+                if !rules.repeat_on_draw {
+                    self.advance();
+                }
+            }
+            Msg::Abort { culprit, .. } => {
+                // otherwise whoever is ahead could abort to win
+                self.forfeit = Some(*culprit);
+                self.finished = true;
+            }
+            _ => unreachable!("not a game result"),
+        }
+
+        if self.hand == rules.hands {
+            self.finished = true;
+        }
+
+        if self.finished {
+            vec![self.end_msg()]
+        } else {
+            self.next_hand = Some(rules.next_hand_delay);
+            vec![self.status_msg(rules)]
+        }
+    }
+
+    // Returns true if the next hand should start now.
+    pub fn beat(&mut self) -> bool {
+        match self.next_hand {
+            Some(0) => {
+                self.next_hand = None;
+                true
+            }
+            Some(n) => {
+                self.next_hand = Some(n - 1);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn advance(&mut self) {
+        self.hand += 1;
+        self.east = 1 - self.east;
+    }
+
+    fn end_msg(&self) -> Msg {
+        let winner = if let Some(culprit) = self.forfeit {
+            Some(1 - culprit)
+        } else if self.points[0] > self.points[1] {
+            Some(0)
+        } else if self.points[1] > self.points[0] {
+            Some(1)
+        } else {
+            None
+        };
+        Msg::MatchEnd {
+            points: self.points,
+            winner,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minefield_core::tiles::Tile;
+
+    fn ron(player: usize, points: usize) -> Msg {
+        Msg::Ron {
+            player,
+            hand: vec![],
+            tile: Tile::M1,
+            limit: 1,
+            yaku: vec![],
+            dora: 0,
+            uradora_ind: Tile::M1,
+            points,
+            details: None,
+        }
+    }
+
+    fn rules() -> Ruleset {
+        Ruleset {
+            hands: 2,
+            ..Ruleset::default()
+        }
+    }
+
+    #[test]
+    fn test_match() {
+        let rules = rules();
+        let mut m = Match::new(0);
+
+        let msgs = m.on_result(&ron(1, 8000), &rules);
+        assert_eq!(
+            msgs,
+            vec![Msg::MatchStatus {
+                hand: 1,
+                hands: 2,
+                honba: 0,
+                points: [-8000, 8000],
+            }]
+        );
+        assert_eq!(m.east(), 1);
+
+        for _ in 0..rules.next_hand_delay {
+            assert!(!m.beat());
+        }
+        assert!(m.beat());
+        assert!(!m.beat());
+
+        let msgs = m.on_result(&ron(0, 12000), &rules);
+        assert_eq!(
+            msgs,
+            vec![Msg::MatchEnd {
+                points: [4000, -4000],
+                winner: Some(0),
+            }]
+        );
+        assert!(m.finished);
+    }
+
+    #[test]
+    fn test_draw() {
+        let rules = rules();
+        let mut m = Match::new(0);
+        m.on_result(&Msg::Draw, &rules);
+        assert_eq!(m.east(), 1);
+        assert_eq!(m.honba, 1);
+
+        m.beat_until_next();
+        let msgs = m.on_result(&ron(1, 8000), &rules);
+        assert_eq!(
+            msgs,
+            vec![Msg::MatchEnd {
+                points: [-8300, 8300],
+                winner: Some(1),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_repeat_on_draw() {
+        let rules = Ruleset {
+            repeat_on_draw: true,
+            ..rules()
+        };
+        let mut m = Match::new(0);
+        m.on_result(&Msg::Draw, &rules);
+        m.beat_until_next();
+        m.on_result(&Msg::Draw, &rules);
+        assert_eq!(m.east(), 0);
+        assert_eq!(m.hand, 0);
+        assert_eq!(m.honba, 2);
+        assert!(!m.finished);
+    }
+
+    #[test]
+    fn test_abort() {
+        let rules = rules();
+        let mut m = Match::new(0);
+        m.on_result(&ron(0, 8000), &rules);
+        m.beat_until_next();
+        let msgs = m.on_result(
+            &Msg::Abort {
+                culprit: 0,
+                description: "time limit exceeded".to_owned(),
+            },
+            &rules,
+        );
+        assert_eq!(
+            msgs,
+            vec![Msg::MatchEnd {
+                points: [8000, -8000],
+                winner: Some(1),
+            }]
+        );
+        assert!(m.finished);
+    }
+
+    impl Match {
+        fn beat_until_next(&mut self) {
+            while !self.beat() {}
+        }
+    }
+}
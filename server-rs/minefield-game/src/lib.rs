@@ -1,11 +1,18 @@
 extern crate failure;
 extern crate failure_derive;
+extern crate log;
 extern crate rusqlite;
 
 extern crate minefield_core;
 
+pub mod bot_player;
+pub mod chat;
 pub mod db;
 pub mod game;
+pub mod game_match;
 pub mod lobby;
 pub mod protocol;
+pub mod queue;
+pub mod rating;
+pub mod record;
 pub mod room;
@@ -1,10 +1,27 @@
 use std::collections::HashMap;
 
 use failure::{Error, Fail};
+use log::info;
 
-use crate::db::Database;
-use crate::protocol::Msg;
-use crate::room::Room;
+use minefield_core::rules::Ruleset;
+use minefield_core::seed::Seeder;
+use minefield_core::strategy::{self, DEFAULT_STRATEGY};
+
+use crate::bot_player::{BotResult, BotTask, BOT_NICK};
+use crate::chat::{self, RateLimiter};
+use crate::db::{Account, Database};
+use crate::protocol::{
+    Capability, ChatMessage, ChatScope, ErrorCode, Msg, Outgoing, PrivateRoom,
+    MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+use crate::queue::{MatchQueue, QueueEntry};
+use crate::rating;
+use crate::room::{Room, RoomError};
+
+// Beats before a queued player gets a bot opponent
+const QUEUE_BOT_DELAY: usize = 30;
+
+pub const MAX_NICK_LENGTH: usize = 30;
 
 #[derive(Debug, Fail)]
 pub enum LobbyError {
@@ -16,13 +33,90 @@ pub enum LobbyError {
     NotJoined,
     #[fail(display = "wrong key")]
     WrongKey,
+    #[fail(display = "invalid rules: {}", _0)]
+    InvalidRules(&'static str),
+    #[fail(display = "nick already taken")]
+    NickTaken,
+    #[fail(display = "invalid nick: {}", _0)]
+    InvalidNick(&'static str),
+    #[fail(display = "wrong token")]
+    WrongToken,
+    #[fail(display = "invalid chat message: {}", _0)]
+    InvalidChat(&'static str),
+    #[fail(display = "too many chat messages")]
+    ChatRateLimited,
+    #[fail(display = "unknown bot strategy: {}", _0)]
+    UnknownStrategy(String),
+}
+
+// What a connected user negotiated in the handshake. Version 0 means the
+// client didn't send Hello.
+#[derive(Default)]
+struct Session {
+    protocol_version: usize,
+    capabilities: Vec<Capability>,
+    // Guest's nick, fixed by the first message using it
+    nick: Option<String>,
+}
+
+impl LobbyError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            LobbyError::UnrecognizedMessage => ErrorCode::UnrecognizedMessage,
+            LobbyError::AlreadyJoined => ErrorCode::AlreadyJoined,
+            LobbyError::NotJoined => ErrorCode::NotJoined,
+            LobbyError::WrongKey => ErrorCode::WrongKey,
+            LobbyError::InvalidRules(_) => ErrorCode::InvalidRules,
+            LobbyError::NickTaken => ErrorCode::NickTaken,
+            LobbyError::InvalidNick(_) => ErrorCode::InvalidNick,
+            LobbyError::WrongToken => ErrorCode::WrongToken,
+            LobbyError::InvalidChat(_) => ErrorCode::InvalidChat,
+            LobbyError::ChatRateLimited => ErrorCode::ChatRateLimited,
+            LobbyError::UnknownStrategy(_) => ErrorCode::UnknownStrategy,
+        }
+    }
+}
+
+// Reply to a request that failed. Anything other than a lobby or room error
+// is a bug on our side, and the details are not sent to the client.
+pub fn error_msg(err: &Error, request: Option<Msg>) -> Msg {
+    let (code, message) = if let Some(err) = err.downcast_ref::<LobbyError>() {
+        (err.code(), err.to_string())
+    } else if let Some(err) = err.downcast_ref::<RoomError>() {
+        (err.code(), err.to_string())
+    } else {
+        (ErrorCode::Internal, "internal error".to_owned())
+    };
+    Msg::Error {
+        code,
+        message,
+        request: request.map(Box::new),
+    }
+}
+
+// Errors after which the connection should be closed: the client sent
+// something that is not a client message at all.
+pub fn is_protocol_violation(err: &Error) -> bool {
+    matches!(
+        err.downcast_ref::<LobbyError>(),
+        Some(LobbyError::UnrecognizedMessage)
+    )
 }
 
 pub struct Lobby {
     database: Database,
     next_user_id: usize,
+    sessions: HashMap<usize, Session>,
     rooms: HashMap<usize, Room>,
     user_to_room: HashMap<usize, usize>,
+    spectator_to_room: HashMap<usize, usize>,
+    accounts: HashMap<usize, Account>,
+    queue: MatchQueue,
+    chat_limiter: RateLimiter,
+    // seeds for new rooms
+    seeder: Seeder,
+    // started with a seed: rooms and bots don't depend on timing
+    reproducible: bool,
 }
 
 impl Lobby {
@@ -36,35 +130,105 @@ impl Lobby {
         Ok(Lobby {
             database,
             next_user_id: 0,
+            sessions: HashMap::new(),
             rooms,
             user_to_room: HashMap::new(),
+            spectator_to_room: HashMap::new(),
+            accounts: HashMap::new(),
+            queue: MatchQueue::new(Some(QUEUE_BOT_DELAY)),
+            chat_limiter: RateLimiter::default(),
+            seeder: Seeder::from_entropy(),
+            reproducible: false,
         })
     }
 
+    // None to never fall back to a bot
+    pub fn set_queue_bot_delay(&mut self, delay: Option<usize>) {
+        self.queue.bot_delay = delay;
+    }
+
+    // Makes new rooms reproducible. Rooms loaded from the database keep
+    // their own seeds. After a restart with the same seed, we continue where
+    // we stopped instead of dealing the same games again.
+    pub fn set_seed(&mut self, seed: u64) -> Result<(), Error> {
+        self.seeder = match self.database.load_seeder()? {
+            Some(seeder) if seeder.seed() == seed => seeder,
+            _ => Seeder::new(seed),
+        };
+        self.reproducible = true;
+        Ok(())
+    }
+
     pub fn connect(&mut self) -> usize {
         let user_id = self.next_user_id;
         self.next_user_id += 1;
+        self.sessions.insert(user_id, Session::default());
         user_id
     }
 
     pub fn disconnect(&mut self, user_id: usize) {
+        self.sessions.remove(&user_id);
+        self.chat_limiter.remove(user_id);
+        self.accounts.remove(&user_id);
+        self.queue.remove(user_id);
         if let Some(room_id) = self.user_to_room.get(&user_id).cloned() {
             let room = self.rooms.get_mut(&room_id).unwrap();
             room.disconnect(user_id);
             self.user_to_room.remove(&user_id);
             self.update_room(room_id);
         }
+        if let Some(room_id) = self.spectator_to_room.remove(&user_id) {
+            if let Some(room) = self.rooms.get_mut(&room_id) {
+                room.stop_spectating(user_id);
+            }
+        }
     }
 
-    pub fn beat(&mut self) -> Vec<(usize, Msg)> {
+    // Beat with the bots' moves chosen right away.
+    pub fn beat(&mut self) -> Vec<Outgoing> {
+        let (mut messages, tasks) = self.beat_deferred();
+        for task in tasks.into_iter() {
+            messages.append(&mut self.bot_moved(task.run()));
+        }
+        messages
+    }
+
+    // Choosing a bot's move can take a while, so it's left to the caller:
+    // run the tasks without holding the lobby, and pass the results to
+    // `bot_moved`.
+    pub fn beat_deferred(&mut self) -> (Vec<Outgoing>, Vec<BotTask>) {
+        self.chat_limiter.beat();
         let mut messages = vec![];
+        let mut tasks = vec![];
         let room_ids: Vec<usize> = self.rooms.keys().copied().collect();
         for room_id in room_ids.iter() {
             let room = self.rooms.get_mut(room_id).unwrap();
-            messages.append(&mut room.beat());
+            let (mut room_messages, room_tasks) = room.beat_deferred();
+            messages.append(&mut room_messages);
+            for mut task in room_tasks.into_iter() {
+                task.room_id = *room_id;
+                tasks.push(task);
+            }
             self.update_room(*room_id);
         }
-        messages
+        for entry in self.queue.beat() {
+            let mut result = self
+                .start_bot_game(entry.user_id, entry.nick, entry.rules, DEFAULT_STRATEGY)
+                .unwrap();
+            messages.append(&mut result);
+        }
+        (self.filter_messages(messages), tasks)
+    }
+
+    pub fn bot_moved(&mut self, result: BotResult) -> Vec<Outgoing> {
+        let room_id = result.room_id;
+        let messages = match self.rooms.get_mut(&room_id) {
+            Some(room) => room.bot_moved(result),
+            // finished in the meantime
+            None => return vec![],
+        };
+        self.update_room(room_id);
+        self.filter_messages(messages)
     }
 
     pub fn debug_dump(&self) -> String {
@@ -72,7 +236,31 @@ impl Lobby {
     }
 
     fn update_room(&mut self, room_id: usize) {
-        let room = self.rooms.get(&room_id).unwrap();
+        let room = self.rooms.get_mut(&room_id).unwrap();
+
+        if let Some(record) = room.take_finished_record() {
+            let player_ids = room.player_ids();
+            self.database
+                .save_game(room_id, &record, player_ids)
+                .unwrap();
+
+            if let ([Some(id0), Some(id1)], Some(result)) = (player_ids, record.result.as_ref()) {
+                if id0 != id1 {
+                    let ratings = self
+                        .database
+                        .update_ratings([id0, id1], rating::score(result))
+                        .unwrap();
+                    room.set_ratings([ratings[0].round() as isize, ratings[1].round() as isize]);
+                    for account in self.accounts.values_mut() {
+                        if account.player_id == id0 {
+                            account.rating = ratings[0];
+                        } else if account.player_id == id1 {
+                            account.rating = ratings[1];
+                        }
+                    }
+                }
+            }
+        }
 
         if room.started() {
             self.database.save_room(room_id, &room).unwrap();
@@ -82,44 +270,338 @@ impl Lobby {
 
         if room.finished() {
             self.rooms.remove(&room_id);
+            self.spectator_to_room.retain(|_, id| *id != room_id);
         }
     }
 
-    pub fn on_message(&mut self, user_id: usize, msg: Msg) -> Result<Vec<(usize, Msg)>, Error> {
+    pub fn on_message(&mut self, user_id: usize, msg: Msg) -> Result<Vec<Outgoing>, Error> {
+        if let Msg::Hello {
+            protocol_version,
+            client,
+            capabilities,
+        } = msg
+        {
+            return Ok(self.hello(user_id, protocol_version, client, capabilities));
+        }
+        if let Some(capability) = msg.capability() {
+            if !self.has_capability(user_id, capability) {
+                let message = format!(
+                    "message requires the {} capability",
+                    serde_json::to_value(capability).unwrap()
+                );
+                return Ok(error_reply(
+                    user_id,
+                    ErrorCode::UnsupportedMessage,
+                    message,
+                    Some(msg),
+                ));
+            }
+        }
+        let messages = self.handle_message(user_id, msg)?;
+        Ok(self.filter_messages(messages))
+    }
+
+    fn handle_message(&mut self, user_id: usize, msg: Msg) -> Result<Vec<Outgoing>, Error> {
         match msg {
             Msg::GetGames => Ok(self.describe_games(user_id)),
-            Msg::NewGame { nick } => self.new_game(user_id, nick),
+            Msg::Register { nick } => self.register(user_id, nick),
+            Msg::Login { token } => self.login(user_id, token),
+            Msg::NewGame {
+                nick,
+                rules,
+                private,
+            } => self.new_game(user_id, nick, rules, private),
+            Msg::NewBotGame {
+                nick,
+                rules,
+                strategy,
+            } => self.new_bot_game(user_id, nick, rules, strategy),
             Msg::CancelNewGame => self.cancel_new_game(user_id),
-            Msg::Join { nick, key } => self.join(user_id, nick, key),
-            Msg::Rejoin { key } => self.rejoin(user_id, key),
-            Msg::Hand { .. } | Msg::Discard { .. } => self.on_room_message(user_id, msg),
+            Msg::QueueForMatch {
+                nick,
+                rules,
+                rating_band,
+            } => self.queue_for_match(user_id, nick, rules, rating_band),
+            Msg::LeaveQueue => self.leave_queue(user_id),
+            Msg::Join {
+                nick,
+                key,
+                password,
+            } => self.join(user_id, nick, key, password),
+            Msg::Rejoin { key, last_seen_seq } => self.rejoin(user_id, key, last_seen_seq),
+            Msg::Spectate { room } => self.spectate(user_id, room),
+            Msg::Chat {
+                nick,
+                scope,
+                message,
+            } => self.chat(user_id, nick, scope, message),
+            Msg::Hand { .. } | Msg::Discard { .. } | Msg::RequestRematch => {
+                self.on_room_message(user_id, msg)
+            }
             _ => Err(LobbyError::UnrecognizedMessage.into()),
         }
     }
 
-    fn describe_games(&self, user_id: usize) -> Vec<(usize, Msg)> {
+    fn hello(
+        &mut self,
+        user_id: usize,
+        protocol_version: usize,
+        client: String,
+        capabilities: Vec<String>,
+    ) -> Vec<Outgoing> {
+        info!(
+            "[{}] hello: {} (version {})",
+            user_id, client, protocol_version
+        );
+        if self.sessions[&user_id].protocol_version > 0 {
+            let message = "handshake already done".to_owned();
+            return error_reply(user_id, ErrorCode::UnsupportedMessage, message, None);
+        }
+        if protocol_version < MIN_PROTOCOL_VERSION || protocol_version > PROTOCOL_VERSION {
+            let message = format!(
+                "unsupported protocol version {}, expected {} to {}",
+                protocol_version, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION
+            );
+            return error_reply(user_id, ErrorCode::UnsupportedVersion, message, None);
+        }
+
+        // Unknown capabilities are ignored, the client will see what's
+        // supported in Welcome.
+        let capabilities: Vec<Capability> = Capability::ALL
+            .iter()
+            .copied()
+            .filter(|c| {
+                capabilities
+                    .iter()
+                    .any(|name| Capability::from_name(name) == Some(*c))
+            })
+            .collect();
+        self.sessions.insert(
+            user_id,
+            Session {
+                protocol_version,
+                capabilities: capabilities.clone(),
+                nick: None,
+            },
+        );
+        vec![(
+            user_id,
+            None,
+            Msg::Welcome {
+                protocol_version,
+                server: format!("minefield {}", env!("CARGO_PKG_VERSION")),
+                capabilities,
+            },
+        )]
+    }
+
+    fn has_capability(&self, user_id: usize, capability: Capability) -> bool {
+        self.sessions
+            .get(&user_id)
+            .is_some_and(|session| session.capabilities.contains(&capability))
+    }
+
+    // Drop messages the recipients didn't negotiate capabilities for.
+    fn filter_messages(&self, messages: Vec<Outgoing>) -> Vec<Outgoing> {
+        messages
+            .into_iter()
+            .filter(|(user_id, _, msg)| match msg.capability() {
+                Some(capability) => self.has_capability(*user_id, capability),
+                None => true,
+            })
+            .collect()
+    }
+
+    fn register(&mut self, user_id: usize, nick: String) -> Result<Vec<Outgoing>, Error> {
+        validate_nick(&nick).map_err(LobbyError::InvalidNick)?;
+        let token = gen_token();
+        let account = self
+            .database
+            .new_account(&nick, &token)?
+            .ok_or(LobbyError::NickTaken)?;
+        Ok(self.logged_in(user_id, account, token))
+    }
+
+    fn login(&mut self, user_id: usize, token: String) -> Result<Vec<Outgoing>, Error> {
+        let account = self
+            .database
+            .find_account(&token)?
+            .ok_or(LobbyError::WrongToken)?;
+        Ok(self.logged_in(user_id, account, token))
+    }
+
+    fn logged_in(&mut self, user_id: usize, account: Account, token: String) -> Vec<Outgoing> {
+        let msg = Msg::LoggedIn {
+            player_id: account.player_id,
+            nick: account.nick.clone(),
+            token,
+            rating: account.rating.round() as isize,
+        };
+        self.accounts.insert(user_id, account);
+        vec![(user_id, None, msg)]
+    }
+
+    // Logged in players always play under their account's nick, and guests
+    // cannot use one. A guest keeps the first nick they use for the whole
+    // session, so that nobody can speak as someone else.
+    fn session_nick(&mut self, user_id: usize, nick: String) -> Result<String, Error> {
+        if let Some(account) = self.accounts.get(&user_id) {
+            return Ok(account.nick.clone());
+        }
+        let session = self.sessions.get_mut(&user_id).unwrap();
+        if let Some(ref bound) = session.nick {
+            return Ok(bound.clone());
+        }
+        if is_bot_nick(&nick) {
+            return Err(LobbyError::InvalidNick("reserved for bots").into());
+        }
+        if self.database.nick_registered(&nick)? {
+            return Err(LobbyError::NickTaken.into());
+        }
+        if !nick.trim().is_empty() {
+            session.nick = Some(nick.clone());
+        }
+        Ok(nick)
+    }
+
+    fn attach_account(accounts: &HashMap<usize, Account>, user_id: usize, room: &mut Room) {
+        if let Some(account) = accounts.get(&user_id) {
+            room.set_account(user_id, account.player_id, account.rating.round() as isize);
+        }
+    }
+
+    // Private rooms are listed only for their creator, who needs the key to
+    // share it.
+    fn describe_games(&self, user_id: usize) -> Vec<Outgoing> {
+        let own_room = self.user_to_room.get(&user_id);
         vec![(
             user_id,
+            None,
             Msg::Games {
                 games: self
                     .rooms
-                    .values()
-                    .filter_map(|room| room.describe())
+                    .iter()
+                    .filter(|(room_id, room)| !room.private() || own_room == Some(room_id))
+                    .filter_map(|(_, room)| room.describe())
                     .collect(),
             },
         )]
     }
 
-    fn new_game(&mut self, user_id: usize, nick: String) -> Result<Vec<(usize, Msg)>, Error> {
+    fn new_game(
+        &mut self,
+        user_id: usize,
+        nick: String,
+        rules: Option<Ruleset>,
+        private: Option<PrivateRoom>,
+    ) -> Result<Vec<Outgoing>, Error> {
         self.ensure_no_room(user_id)?;
-        let room = Room::new(user_id, nick);
+        let rules = rules.unwrap_or_default();
+        rules.validate().map_err(LobbyError::InvalidRules)?;
+        let nick = self.session_nick(user_id, nick)?;
+        let mut room = self.new_room(user_id, nick, rules);
+        room.set_private(private);
         let room_id = self.database.new_room(&room).unwrap();
         self.rooms.insert(room_id, room);
         self.user_to_room.insert(user_id, room_id);
         Ok(self.describe_games(user_id))
     }
 
-    fn cancel_new_game(&mut self, user_id: usize) -> Result<Vec<(usize, Msg)>, Error> {
+    fn new_bot_game(
+        &mut self,
+        user_id: usize,
+        nick: String,
+        rules: Option<Ruleset>,
+        strategy: Option<String>,
+    ) -> Result<Vec<Outgoing>, Error> {
+        self.ensure_no_room(user_id)?;
+        let rules = rules.unwrap_or_default();
+        rules.validate().map_err(LobbyError::InvalidRules)?;
+        let strategy = strategy.unwrap_or_else(|| DEFAULT_STRATEGY.to_owned());
+        if strategy::by_name(&strategy).is_none() {
+            return Err(LobbyError::UnknownStrategy(strategy).into());
+        }
+        let nick = self.session_nick(user_id, nick)?;
+        self.start_bot_game(user_id, nick, rules, &strategy)
+    }
+
+    fn start_bot_game(
+        &mut self,
+        user_id: usize,
+        nick: String,
+        rules: Ruleset,
+        strategy: &str,
+    ) -> Result<Vec<Outgoing>, Error> {
+        let mut room = self.new_room(user_id, nick, rules);
+        let result = room.connect_bot(strategy)?;
+        let room_id = self.database.new_room(&room).unwrap();
+        self.rooms.insert(room_id, room);
+        self.user_to_room.insert(user_id, room_id);
+        self.update_room(room_id);
+        Ok(result)
+    }
+
+    fn queue_for_match(
+        &mut self,
+        user_id: usize,
+        nick: String,
+        rules: Option<Ruleset>,
+        rating_band: Option<isize>,
+    ) -> Result<Vec<Outgoing>, Error> {
+        self.ensure_no_room(user_id)?;
+        let rules = rules.unwrap_or_default();
+        rules.validate().map_err(LobbyError::InvalidRules)?;
+        let entry = QueueEntry {
+            user_id,
+            nick: self.session_nick(user_id, nick)?,
+            rules,
+            rating: self
+                .accounts
+                .get(&user_id)
+                .map(|account| account.rating.round() as isize),
+            rating_band,
+            waited: 0,
+        };
+        match self.queue.push(entry) {
+            Some((first, second)) => self.start_match(first, second),
+            None => Ok(vec![(user_id, None, Msg::Queued)]),
+        }
+    }
+
+    fn leave_queue(&mut self, user_id: usize) -> Result<Vec<Outgoing>, Error> {
+        self.queue.remove(user_id).ok_or(LobbyError::NotJoined)?;
+        Ok(self.describe_games(user_id))
+    }
+
+    fn start_match(
+        &mut self,
+        first: QueueEntry,
+        second: QueueEntry,
+    ) -> Result<Vec<Outgoing>, Error> {
+        let mut room = self.new_room(first.user_id, first.nick, first.rules);
+        let result = room.connect(second.user_id, second.nick)?;
+        Self::attach_account(&self.accounts, second.user_id, &mut room);
+        let room_id = self.database.new_room(&room).unwrap();
+        self.rooms.insert(room_id, room);
+        self.user_to_room.insert(first.user_id, room_id);
+        self.user_to_room.insert(second.user_id, room_id);
+        self.update_room(room_id);
+        Ok(result)
+    }
+
+    // A room with the next seed, not saved yet.
+    fn new_room(&mut self, user_id: usize, nick: String, rules: Ruleset) -> Room {
+        let seed = self.seeder.next_seed();
+        if self.reproducible {
+            self.database.save_seeder(&self.seeder).unwrap();
+        }
+        let mut room = Room::with_seed(user_id, nick, rules, seed);
+        room.set_reproducible(self.reproducible);
+        Self::attach_account(&self.accounts, user_id, &mut room);
+        room
+    }
+
+    fn cancel_new_game(&mut self, user_id: usize) -> Result<Vec<Outgoing>, Error> {
         let (room_id, room) = self.ensure_room_mut(user_id)?;
         room.disconnect(user_id);
         self.user_to_room.remove(&user_id);
@@ -132,18 +614,37 @@ impl Lobby {
         user_id: usize,
         nick: String,
         key: String,
-    ) -> Result<Vec<(usize, Msg)>, Error> {
+        password: Option<String>,
+    ) -> Result<Vec<Outgoing>, Error> {
+        if self.user_to_room.contains_key(&user_id) {
+            return Err(LobbyError::AlreadyJoined.into());
+        }
+        let nick = self.session_nick(user_id, nick)?;
         let found = self.rooms.iter_mut().find(|(_, room)| room.room_key == key);
         if let Some((room_id, room)) = found {
+            room.check_access(&nick, password.as_deref())?;
             let result = room.connect(user_id, nick)?;
-            self.user_to_room.insert(user_id, *room_id);
+            Self::attach_account(&self.accounts, user_id, room);
+            let room_id = *room_id;
+            // Joining a friend's room gives up the place in the queue.
+            self.queue.remove(user_id);
+            self.user_to_room.insert(user_id, room_id);
+            self.update_room(room_id);
             Ok(result)
         } else {
             Err(LobbyError::WrongKey.into())
         }
     }
 
-    fn rejoin(&mut self, user_id: usize, key: String) -> Result<Vec<(usize, Msg)>, Error> {
+    fn rejoin(
+        &mut self,
+        user_id: usize,
+        key: String,
+        last_seen_seq: Option<usize>,
+    ) -> Result<Vec<Outgoing>, Error> {
+        if self.user_to_room.contains_key(&user_id) {
+            return Err(LobbyError::AlreadyJoined.into());
+        }
         let found = self.rooms.iter_mut().find_map(|(room_id, room)| {
             for i in 0..2 {
                 if room.player_keys[i] == key {
@@ -153,7 +654,9 @@ impl Lobby {
             None
         });
         if let Some((room_id, i, room)) = found {
-            let result = room.rejoin(user_id, i)?;
+            let result = room.rejoin(user_id, i, last_seen_seq)?;
+            // Back to the old game, so not waiting for a new one.
+            self.queue.remove(user_id);
             self.user_to_room.insert(user_id, *room_id);
             Ok(result)
         } else {
@@ -161,7 +664,60 @@ impl Lobby {
         }
     }
 
-    fn on_room_message(&mut self, user_id: usize, msg: Msg) -> Result<Vec<(usize, Msg)>, Error> {
+    fn spectate(&mut self, user_id: usize, key: String) -> Result<Vec<Outgoing>, Error> {
+        self.ensure_no_room(user_id)?;
+        if self.spectator_to_room.contains_key(&user_id) {
+            return Err(LobbyError::AlreadyJoined.into());
+        }
+        let found = self.rooms.iter_mut().find(|(_, room)| room.room_key == key);
+        if let Some((room_id, room)) = found {
+            let result = room.spectate(user_id)?;
+            self.spectator_to_room.insert(user_id, *room_id);
+            Ok(result)
+        } else {
+            Err(LobbyError::WrongKey.into())
+        }
+    }
+
+    fn chat(
+        &mut self,
+        user_id: usize,
+        nick: String,
+        scope: ChatScope,
+        message: ChatMessage,
+    ) -> Result<Vec<Outgoing>, Error> {
+        chat::validate(&message).map_err(LobbyError::InvalidChat)?;
+        if !self.chat_limiter.allow(user_id) {
+            return Err(LobbyError::ChatRateLimited.into());
+        }
+        match scope {
+            ChatScope::Lobby => {
+                let nick = self.session_nick(user_id, nick)?;
+                if nick.trim().is_empty() {
+                    return Err(LobbyError::InvalidChat("no nick").into());
+                }
+                let msg = Msg::ChatReceived {
+                    scope,
+                    nick,
+                    message,
+                };
+                let mut user_ids: Vec<usize> = self.sessions.keys().copied().collect();
+                user_ids.sort_unstable();
+                Ok(user_ids
+                    .into_iter()
+                    .map(|user_id| (user_id, None, msg.clone()))
+                    .collect())
+            }
+            ChatScope::Room => {
+                let (room_id, room) = self.ensure_room_mut(user_id)?;
+                let result = room.chat(user_id, message)?;
+                self.update_room(room_id);
+                Ok(result)
+            }
+        }
+    }
+
+    fn on_room_message(&mut self, user_id: usize, msg: Msg) -> Result<Vec<Outgoing>, Error> {
         let (room_id, room) = self.ensure_room_mut(user_id)?;
         let result = room.on_message(user_id, msg)?;
         self.update_room(room_id);
@@ -169,7 +725,7 @@ impl Lobby {
     }
 
     fn ensure_no_room(&self, user_id: usize) -> Result<(), Error> {
-        if self.rooms.get(&user_id).is_some() {
+        if self.user_to_room.contains_key(&user_id) || self.queue.contains(user_id) {
             Err(LobbyError::AlreadyJoined.into())
         } else {
             Ok(())
@@ -186,10 +742,57 @@ impl Lobby {
     }
 }
 
+fn error_reply(
+    user_id: usize,
+    code: ErrorCode,
+    message: String,
+    request: Option<Msg>,
+) -> Vec<Outgoing> {
+    vec![(
+        user_id,
+        None,
+        Msg::Error {
+            code,
+            message,
+            request: request.map(Box::new),
+        },
+    )]
+}
+
+// Not seeded: tokens are secret, and stay so even with a known seed.
+fn validate_nick(nick: &str) -> Result<(), &'static str> {
+    if nick.trim().is_empty() {
+        Err("empty nick")
+    } else if nick.trim() != nick {
+        Err("leading or trailing spaces")
+    } else if nick.chars().count() > MAX_NICK_LENGTH {
+        Err("nick too long")
+    } else if is_bot_nick(nick) {
+        Err("reserved for bots")
+    } else {
+        Ok(())
+    }
+}
+
+fn is_bot_nick(nick: &str) -> bool {
+    nick.trim().eq_ignore_ascii_case(BOT_NICK)
+}
+
+fn gen_token() -> String {
+    use rand::distributions::Alphanumeric;
+    use rand::Rng;
+
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::protocol::PGame;
+    use crate::protocol::{PGame, Phrase};
+    use minefield_core::tiles::Tile;
 
     #[test]
     fn new_game_and_join() {
@@ -202,21 +805,23 @@ mod tests {
                 55,
                 Msg::NewGame {
                     nick: "Akagi".to_owned(),
+                    rules: None,
+                    private: None,
                 },
             )
             .unwrap();
         assert_eq!(messages.len(), 1);
-        assert!(matches!(messages[0], (55, Msg::Games { .. })));
+        assert!(matches!(messages[0], (55, _, Msg::Games { .. })));
 
         assert_eq!(lobby.connect(), 56);
         let messages = lobby.on_message(56, Msg::GetGames).unwrap();
         assert_eq!(messages.len(), 1);
         let key = match &messages[0] {
-            (user_id, Msg::Games { games }) => {
+            (user_id, _, Msg::Games { games }) => {
                 assert_eq!(*user_id, 56);
                 assert_eq!(games.len(), 1);
                 match &games[0] {
-                    PGame::Player { nick, key } => {
+                    PGame::Player { nick, key, .. } => {
                         assert_eq!(nick, "Akagi");
                         key.clone()
                     }
@@ -232,9 +837,11 @@ mod tests {
                 Msg::Join {
                     nick: "Washizu".to_owned(),
                     key,
+                    password: None,
                 },
             )
             .unwrap();
+        let messages = events(messages);
         assert_eq!(messages.len(), 6);
         assert!(matches!(messages[0], (55, Msg::Room { .. })));
         assert!(matches!(messages[1], (56, Msg::Room { .. })));
@@ -250,11 +857,647 @@ mod tests {
 
         lobby.disconnect(56);
         assert_eq!(lobby.connect(), 57);
-        let messages = lobby.on_message(57, Msg::Rejoin { key }).unwrap();
+        let messages = lobby
+            .on_message(
+                57,
+                Msg::Rejoin {
+                    key,
+                    last_seen_seq: None,
+                },
+            )
+            .unwrap();
+        let messages = events(messages);
         assert_eq!(messages.len(), 4);
         assert!(matches!(messages[0], (57, Msg::Replay { .. })));
         assert!(matches!(messages[1], (57, Msg::Replay { .. })));
         assert!(matches!(messages[2], (57, Msg::Replay { .. })));
         assert!(matches!(messages[3], (57, Msg::Replay { .. })));
     }
+
+    // Drops event numbers
+    fn events(messages: Vec<Outgoing>) -> Vec<(usize, Msg)> {
+        messages
+            .into_iter()
+            .map(|(user_id, _, msg)| (user_id, msg))
+            .collect()
+    }
+
+    // Connects a client supporting everything
+    fn connect(lobby: &mut Lobby) -> usize {
+        let user_id = lobby.connect();
+        let hello = Msg::Hello {
+            protocol_version: PROTOCOL_VERSION,
+            client: "test".to_owned(),
+            capabilities: Capability::ALL
+                .iter()
+                .map(|c| {
+                    serde_json::to_value(c)
+                        .unwrap()
+                        .as_str()
+                        .unwrap()
+                        .to_owned()
+                })
+                .collect(),
+        };
+        lobby.on_message(user_id, hello).unwrap();
+        user_id
+    }
+
+    fn login(lobby: &mut Lobby, msg: Msg) -> (String, isize) {
+        let user_id = connect(lobby);
+        match lobby.on_message(user_id, msg).unwrap()[0].2 {
+            Msg::LoggedIn {
+                ref token, rating, ..
+            } => (token.clone(), rating),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn accounts_and_ratings() {
+        let mut lobby = Lobby::new();
+        let register = |nick: &str| Msg::Register {
+            nick: nick.to_owned(),
+        };
+        let (token_a, rating) = login(&mut lobby, register("Akagi"));
+        assert_eq!(rating, 1500);
+        let (token_b, _) = login(&mut lobby, register("Washizu"));
+        let user_id = connect(&mut lobby);
+        assert!(lobby.on_message(user_id, register("Akagi")).is_err());
+        let long = "x".repeat(MAX_NICK_LENGTH + 1);
+        for nick in &["", "  ", " Ichikawa", "bot", &long] {
+            let err = lobby.on_message(user_id, register(nick)).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<LobbyError>(),
+                Some(LobbyError::InvalidNick(_))
+            ));
+        }
+        // guests cannot use a registered nick
+        let new_game = |nick: &str| Msg::NewGame {
+            nick: nick.to_owned(),
+            rules: None,
+            private: None,
+        };
+        let err = lobby.on_message(user_id, new_game("Akagi")).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<LobbyError>(),
+            Some(LobbyError::NickTaken)
+        ));
+        // nor the bot's
+        let err = lobby.on_message(user_id, new_game("Bot")).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<LobbyError>(),
+            Some(LobbyError::InvalidNick(_))
+        ));
+        let wrong = Msg::Login {
+            token: "xxx".to_owned(),
+        };
+        assert!(lobby.on_message(user_id, wrong).is_err());
+
+        let a = connect(&mut lobby);
+        lobby.on_message(a, Msg::Login { token: token_a }).unwrap();
+        let b = connect(&mut lobby);
+        lobby.on_message(b, Msg::Login { token: token_b }).unwrap();
+
+        // the account's nick is used
+        let key = match &lobby.on_message(a, new_game("Someone")).unwrap()[0].2 {
+            Msg::Games { games } => match &games[0] {
+                PGame::Player { nick, key, rating } => {
+                    assert_eq!(nick, "Akagi");
+                    assert_eq!(*rating, Some(1500));
+                    key.clone()
+                }
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        let join = Msg::Join {
+            nick: "Washizu".to_owned(),
+            key,
+            password: None,
+        };
+        lobby.on_message(b, join).unwrap();
+
+        // Akagi aborts the game
+        lobby
+            .on_message(a, Msg::Discard { tile: Tile::M1 })
+            .unwrap();
+        assert_eq!(lobby.accounts[&a].rating, 1484.0);
+        assert_eq!(lobby.accounts[&b].rating, 1516.0);
+        let room_id = lobby.user_to_room[&a];
+        assert_eq!(lobby.database.player_summary("Akagi").unwrap().aborts, 1);
+        match lobby.rooms[&room_id].record() {
+            Some(record) => assert_eq!(record.players[0].nick, "Akagi"),
+            None => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn queue() {
+        let mut lobby = Lobby::new();
+        lobby.set_queue_bot_delay(Some(2));
+        let queue = |nick: &str| Msg::QueueForMatch {
+            nick: nick.to_owned(),
+            rules: None,
+            rating_band: None,
+        };
+
+        let a = connect(&mut lobby);
+        assert_eq!(
+            lobby.on_message(a, queue("Akagi")).unwrap(),
+            vec![(a, None, Msg::Queued)]
+        );
+        assert!(lobby.on_message(a, queue("Akagi")).is_err());
+        assert!(lobby.on_message(a, Msg::LeaveQueue).is_ok());
+        assert!(lobby.on_message(a, Msg::LeaveQueue).is_err());
+        lobby.on_message(a, queue("Akagi")).unwrap();
+
+        let b = connect(&mut lobby);
+        let messages = events(lobby.on_message(b, queue("Washizu")).unwrap());
+        assert!(matches!(messages[0], (_, Msg::Room { you: 0, .. })));
+        assert!(matches!(messages[1], (_, Msg::Room { you: 1, .. })));
+        assert_eq!(lobby.user_to_room[&a], lobby.user_to_room[&b]);
+
+        // nobody else comes, play against the bot
+        let c = connect(&mut lobby);
+        lobby.on_message(c, queue("Ichikawa")).unwrap();
+        lobby.beat();
+        let messages = events(lobby.beat());
+        assert!(messages.contains(&(
+            c,
+            Msg::Room {
+                you: 0,
+                nicks: ["Ichikawa".to_owned(), "Bot".to_owned()],
+                key: lobby.rooms[&lobby.user_to_room[&c]].player_keys[0].clone(),
+            }
+        )));
+    }
+
+    #[test]
+    fn private_game() {
+        let mut lobby = Lobby::new();
+        let a = connect(&mut lobby);
+        let messages = lobby
+            .on_message(
+                a,
+                Msg::NewGame {
+                    nick: "Akagi".to_owned(),
+                    rules: None,
+                    private: Some(PrivateRoom {
+                        password: Some("secret".to_owned()),
+                        invited: vec![],
+                    }),
+                },
+            )
+            .unwrap();
+        // the creator still sees the room, to get the key
+        let key = match &messages[0] {
+            (_, _, Msg::Games { games }) => match &games[0] {
+                PGame::Player { key, .. } => key.clone(),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+
+        let b = connect(&mut lobby);
+        assert_eq!(
+            lobby.on_message(b, Msg::GetGames).unwrap(),
+            vec![(b, None, Msg::Games { games: vec![] })]
+        );
+        let join = |password: Option<&str>| Msg::Join {
+            nick: "Washizu".to_owned(),
+            key: key.clone(),
+            password: password.map(str::to_owned),
+        };
+        assert!(lobby.on_message(b, join(None)).is_err());
+        assert!(lobby.on_message(b, join(Some("guess"))).is_err());
+        let messages = events(lobby.on_message(b, join(Some("secret"))).unwrap());
+        assert!(matches!(messages[1], (_, Msg::Room { you: 1, .. })));
+        // already playing
+        let c = connect(&mut lobby);
+        assert!(lobby.on_message(a, join(Some("secret"))).is_err());
+        assert!(lobby.on_message(c, join(Some("secret"))).is_err());
+    }
+
+    #[test]
+    fn private_game_invite() {
+        let mut lobby = Lobby::new();
+        let (token, _) = login(
+            &mut lobby,
+            Msg::Register {
+                nick: "Washizu".to_owned(),
+            },
+        );
+        let a = connect(&mut lobby);
+        let messages = lobby
+            .on_message(
+                a,
+                Msg::NewGame {
+                    nick: "Akagi".to_owned(),
+                    rules: None,
+                    private: Some(PrivateRoom {
+                        password: None,
+                        invited: vec!["Washizu".to_owned()],
+                    }),
+                },
+            )
+            .unwrap();
+        let key = match &messages[0] {
+            (_, _, Msg::Games { games }) => match &games[0] {
+                PGame::Player { key, .. } => key.clone(),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+        let join = |nick: &str| Msg::Join {
+            nick: nick.to_owned(),
+            key: key.clone(),
+            password: None,
+        };
+
+        // a guest cannot claim the invite
+        let b = connect(&mut lobby);
+        assert!(lobby.on_message(b, join("Washizu")).is_err());
+        assert!(lobby.on_message(b, join("Ichikawa")).is_err());
+
+        // the account can, whatever nick it sends
+        lobby.on_message(b, Msg::Login { token }).unwrap();
+        let messages = events(lobby.on_message(b, join("Someone")).unwrap());
+        assert!(matches!(messages[1], (_, Msg::Room { you: 1, .. })));
+    }
+
+    #[test]
+    fn join_from_queue() {
+        let mut lobby = Lobby::new();
+        let a = connect(&mut lobby);
+        let messages = lobby
+            .on_message(
+                a,
+                Msg::NewGame {
+                    nick: "Akagi".to_owned(),
+                    rules: None,
+                    private: None,
+                },
+            )
+            .unwrap();
+        let key = match &messages[0] {
+            (_, _, Msg::Games { games }) => match &games[0] {
+                PGame::Player { key, .. } => key.clone(),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+
+        let b = connect(&mut lobby);
+        let queue = Msg::QueueForMatch {
+            nick: "Washizu".to_owned(),
+            rules: None,
+            rating_band: None,
+        };
+        lobby.on_message(b, queue).unwrap();
+        let join = Msg::Join {
+            nick: "Washizu".to_owned(),
+            key,
+            password: None,
+        };
+        lobby.on_message(b, join).unwrap();
+        assert!(!lobby.queue.contains(b));
+        assert_eq!(lobby.user_to_room[&a], lobby.user_to_room[&b]);
+    }
+
+    #[test]
+    fn queue_kept_on_failed_join() {
+        let mut lobby = Lobby::new();
+        let a = connect(&mut lobby);
+        let queue = Msg::QueueForMatch {
+            nick: "Akagi".to_owned(),
+            rules: None,
+            rating_band: None,
+        };
+        lobby.on_message(a, queue).unwrap();
+
+        let join = Msg::Join {
+            nick: "Akagi".to_owned(),
+            key: "nope".to_owned(),
+            password: None,
+        };
+        assert!(lobby.on_message(a, join).is_err());
+        assert!(lobby.queue.contains(a));
+
+        let spectate = Msg::Spectate {
+            room: "nope".to_owned(),
+        };
+        assert!(lobby.on_message(a, spectate).is_err());
+        assert!(lobby.queue.contains(a));
+        assert!(!lobby.spectator_to_room.contains_key(&a));
+    }
+
+    #[test]
+    fn rejoin_from_queue() {
+        let mut lobby = Lobby::new();
+        let a = connect(&mut lobby);
+        let messages = lobby
+            .on_message(
+                a,
+                Msg::NewGame {
+                    nick: "Akagi".to_owned(),
+                    rules: None,
+                    private: None,
+                },
+            )
+            .unwrap();
+        let key = match &messages[0] {
+            (_, _, Msg::Games { games }) => match &games[0] {
+                PGame::Player { key, .. } => key.clone(),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+
+        let b = connect(&mut lobby);
+        let join = Msg::Join {
+            nick: "Washizu".to_owned(),
+            key,
+            password: None,
+        };
+        let messages = events(lobby.on_message(b, join).unwrap());
+        let key = messages
+            .into_iter()
+            .find_map(|(user_id, msg)| match msg {
+                Msg::Room { key, .. } if user_id == b => Some(key),
+                _ => None,
+            })
+            .unwrap();
+        let room_id = lobby.user_to_room[&b];
+        lobby.disconnect(b);
+
+        let c = connect(&mut lobby);
+        let queue = Msg::QueueForMatch {
+            nick: "Washizu".to_owned(),
+            rules: None,
+            rating_band: None,
+        };
+        lobby.on_message(c, queue).unwrap();
+        let rejoin = Msg::Rejoin {
+            key: key.clone(),
+            last_seen_seq: None,
+        };
+        lobby.on_message(c, rejoin).unwrap();
+        assert!(!lobby.queue.contains(c));
+        assert_eq!(lobby.user_to_room[&c], room_id);
+
+        // Already seated, so no second room
+        let rejoin = Msg::Rejoin {
+            key,
+            last_seen_seq: None,
+        };
+        assert!(lobby.on_message(c, rejoin).is_err());
+        assert_eq!(lobby.user_to_room[&c], room_id);
+    }
+
+    #[test]
+    fn chat() {
+        let mut lobby = Lobby::new();
+        let a = connect(&mut lobby);
+        let b = connect(&mut lobby);
+        let say = |scope, text: &str| Msg::Chat {
+            nick: "Akagi".to_owned(),
+            scope,
+            message: ChatMessage::Text(text.to_owned()),
+        };
+
+        let messages = lobby.on_message(a, say(ChatScope::Lobby, "hi")).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1].0, b);
+        assert!(lobby.on_message(a, say(ChatScope::Lobby, "")).is_err());
+        assert!(lobby
+            .on_message(a, say(ChatScope::Lobby, &"x".repeat(chat::MAX_LENGTH + 1)))
+            .is_err());
+        // not in a room
+        assert!(lobby.on_message(a, say(ChatScope::Room, "hi")).is_err());
+
+        let messages = lobby
+            .on_message(
+                a,
+                Msg::NewBotGame {
+                    nick: "Akagi".to_owned(),
+                    rules: None,
+                    strategy: None,
+                },
+            )
+            .unwrap();
+        let messages = events(messages);
+        let key = match &messages[0].1 {
+            Msg::Room { key, .. } => key.clone(),
+            _ => unreachable!(),
+        };
+        let messages = lobby
+            .on_message(
+                a,
+                Msg::Chat {
+                    nick: String::new(),
+                    scope: ChatScope::Room,
+                    message: ChatMessage::Phrase(Phrase::Thinking),
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            events(messages),
+            vec![(
+                a,
+                Msg::ChatReceived {
+                    scope: ChatScope::Room,
+                    nick: "Akagi".to_owned(),
+                    message: ChatMessage::Phrase(Phrase::Thinking),
+                }
+            )]
+        );
+
+        // rate limited
+        assert!(lobby.on_message(a, say(ChatScope::Room, "hi")).is_ok());
+        assert!(lobby.on_message(a, say(ChatScope::Room, "hi")).is_ok());
+        assert!(lobby.on_message(a, say(ChatScope::Room, "hi")).is_err());
+
+        // a guest keeps their nick for the session
+        let say_as = |nick: &str| Msg::Chat {
+            nick: nick.to_owned(),
+            scope: ChatScope::Lobby,
+            message: ChatMessage::Text("hi".to_owned()),
+        };
+        lobby.on_message(b, say_as("Washizu")).unwrap();
+        let messages = lobby.on_message(b, say_as("Akagi")).unwrap();
+        assert!(messages.iter().all(|(_, _, msg)| matches!(
+            msg,
+            Msg::ChatReceived { nick, .. } if nick == "Washizu"
+        )));
+
+        // replayed on rejoin
+        lobby.disconnect(a);
+        let c = connect(&mut lobby);
+        let messages = lobby
+            .on_message(
+                c,
+                Msg::Rejoin {
+                    key,
+                    last_seen_seq: None,
+                },
+            )
+            .unwrap();
+        assert!(events(messages).iter().any(|(_, msg)| match msg {
+            Msg::Replay { msg } => matches!(**msg, Msg::ChatReceived { .. }),
+            _ => false,
+        }));
+    }
+
+    #[test]
+    fn handshake() {
+        let mut lobby = Lobby::new();
+        let a = lobby.connect();
+        let hello = |protocol_version| Msg::Hello {
+            protocol_version,
+            client: "test".to_owned(),
+            capabilities: vec!["chat".to_owned(), "teleport".to_owned()],
+        };
+        assert!(matches!(
+            lobby.on_message(a, hello(PROTOCOL_VERSION + 1)).unwrap()[0],
+            (
+                _,
+                _,
+                Msg::Error {
+                    code: ErrorCode::UnsupportedVersion,
+                    ..
+                }
+            )
+        ));
+        assert_eq!(
+            lobby.on_message(a, hello(PROTOCOL_VERSION)).unwrap(),
+            vec![(
+                a,
+                None,
+                Msg::Welcome {
+                    protocol_version: PROTOCOL_VERSION,
+                    server: format!("minefield {}", env!("CARGO_PKG_VERSION")),
+                    capabilities: vec![Capability::Chat],
+                }
+            )]
+        );
+        assert!(matches!(
+            lobby.on_message(a, hello(PROTOCOL_VERSION)).unwrap()[0],
+            (
+                _,
+                _,
+                Msg::Error {
+                    code: ErrorCode::UnsupportedMessage,
+                    ..
+                }
+            )
+        ));
+
+        // no capability for bots
+        let new_bot_game = Msg::NewBotGame {
+            nick: "Akagi".to_owned(),
+            rules: None,
+            strategy: None,
+        };
+        assert!(matches!(
+            lobby.on_message(a, new_bot_game).unwrap()[0],
+            (
+                _,
+                _,
+                Msg::Error {
+                    code: ErrorCode::UnsupportedMessage,
+                    ..
+                }
+            )
+        ));
+
+        // an old client doesn't get chat
+        let b = lobby.connect();
+        let chat = Msg::Chat {
+            nick: "Akagi".to_owned(),
+            scope: ChatScope::Lobby,
+            message: ChatMessage::Phrase(Phrase::Hello),
+        };
+        let messages = lobby.on_message(a, chat).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].0, a);
+        assert!(lobby.on_message(b, Msg::GetGames).is_ok());
+    }
+
+    #[test]
+    fn seed() {
+        let new_room_seed = |lobby: &mut Lobby| {
+            let a = connect(lobby);
+            let new_game = Msg::NewGame {
+                nick: "Akagi".to_owned(),
+                rules: None,
+                private: None,
+            };
+            lobby.on_message(a, new_game).unwrap();
+            lobby.rooms[&lobby.user_to_room[&a]].seed()
+        };
+
+        let mut lobby = Lobby::new();
+        lobby.set_seed(7).unwrap();
+        let seed = new_room_seed(&mut lobby);
+        let mut other = Lobby::new();
+        other.set_seed(7).unwrap();
+        assert_eq!(new_room_seed(&mut other), seed);
+
+        // restarted on the same database: no repeated games
+        lobby.set_seed(7).unwrap();
+        let next_seed = new_room_seed(&mut lobby);
+        assert_ne!(next_seed, seed);
+        assert_eq!(new_room_seed(&mut other), next_seed);
+    }
+
+    #[test]
+    fn errors() {
+        let mut lobby = Lobby::new();
+        let a = lobby.connect();
+        let join = Msg::Join {
+            nick: "Akagi".to_owned(),
+            key: "xxx".to_owned(),
+            password: None,
+        };
+        let err = lobby.on_message(a, join.clone()).unwrap_err();
+        assert!(!is_protocol_violation(&err));
+        assert_eq!(
+            error_msg(&err, Some(join.clone())),
+            Msg::Error {
+                code: ErrorCode::WrongKey,
+                message: "wrong key".to_owned(),
+                request: Some(Box::new(join)),
+            }
+        );
+
+        let err = lobby
+            .on_message(a, Msg::Discard { tile: Tile::M1 })
+            .unwrap_err();
+        assert!(matches!(
+            error_msg(&err, None),
+            Msg::Error {
+                code: ErrorCode::NotJoined,
+                ..
+            }
+        ));
+
+        let new_bot_game = Msg::NewBotGame {
+            nick: "Akagi".to_owned(),
+            rules: None,
+            strategy: Some("clairvoyant".to_owned()),
+        };
+        let b = connect(&mut lobby);
+        let err = lobby.on_message(b, new_bot_game).unwrap_err();
+        assert!(matches!(
+            error_msg(&err, None),
+            Msg::Error {
+                code: ErrorCode::UnknownStrategy,
+                ..
+            }
+        ));
+
+        let err = lobby.on_message(a, Msg::Draw).unwrap_err();
+        assert!(is_protocol_violation(&err));
+    }
 }
@@ -2,9 +2,18 @@
 
 use serde::{Deserialize, Serialize};
 
+use minefield_core::fu::FuBreakdown;
+use minefield_core::rules::Ruleset;
 use minefield_core::tiles::Tile;
 use minefield_core::yaku::Yaku;
 
+// Version of the protocol, sent in Hello and Welcome. Clients that don't
+// send Hello are assumed to speak version 0, and get no capabilities.
+//
+// 1: handshake and capabilities
+pub const PROTOCOL_VERSION: usize = 1;
+pub const MIN_PROTOCOL_VERSION: usize = 1;
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
@@ -15,29 +24,96 @@ pub enum Msg {
     },
 
     // client messages
+    Hello {
+        protocol_version: usize,
+        client: String,
+        #[serde(default)]
+        capabilities: Vec<String>,
+    },
     GetGames,
+    Register {
+        nick: String,
+    },
+    Login {
+        token: String,
+    },
     NewGame {
         nick: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rules: Option<Ruleset>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        private: Option<PrivateRoom>,
     },
     Rejoin {
         key: String,
+        // resume after this event, instead of replaying everything
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        last_seen_seq: Option<usize>,
     },
     Join {
         nick: String,
         key: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        password: Option<String>,
+    },
+    NewBotGame {
+        nick: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rules: Option<Ruleset>,
+        // name of the bot's strategy (see minefield_core::strategy)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        strategy: Option<String>,
     },
     CancelNewGame,
+    QueueForMatch {
+        nick: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rules: Option<Ruleset>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rating_band: Option<isize>,
+    },
+    LeaveQueue,
     Hand {
         hand: Vec<Tile>,
     },
     Discard {
         tile: Tile,
     },
+    RequestRematch,
+    Spectate {
+        room: String,
+    },
+    Chat {
+        // only used in the lobby, players in a room chat under their nicks
+        #[serde(default)]
+        nick: String,
+        scope: ChatScope,
+        message: ChatMessage,
+    },
 
     // server messages
+    Welcome {
+        protocol_version: usize,
+        server: String,
+        capabilities: Vec<Capability>,
+    },
+    Error {
+        code: ErrorCode,
+        message: String,
+        // the message that caused the error, if it could be parsed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        request: Option<Box<Msg>>,
+    },
     Games {
         games: Vec<PGame>,
     },
+    Queued,
+    LoggedIn {
+        player_id: usize,
+        nick: String,
+        token: String,
+        rating: isize,
+    },
     Room {
         you: usize,
         nicks: [String; 2],
@@ -60,6 +136,20 @@ pub enum Msg {
         player: usize,
         tile: Tile,
     },
+    // Current state of the game, for resuming when the missed events are
+    // no longer available.
+    Snapshot {
+        you: usize,
+        east: usize,
+        dora_ind: Tile,
+        // tiles left to choose from
+        tiles: Vec<Tile>,
+        hand: Vec<Tile>,
+        discards: [Vec<Tile>; 2],
+        phase_two: bool,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        result: Option<Box<Msg>>,
+    },
     Ron {
         player: usize,
         hand: Vec<Tile>,
@@ -69,12 +159,204 @@ pub enum Msg {
         dora: usize,
         uradora_ind: Tile,
         points: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        details: Option<ScoreDetails>,
     },
     Draw,
     Abort {
         culprit: usize,
         description: String,
     },
+    MatchStatus {
+        hand: usize,
+        hands: usize,
+        honba: usize,
+        points: [isize; 2],
+    },
+    MatchEnd {
+        points: [isize; 2],
+        winner: Option<usize>,
+    },
+    RematchOffered {
+        player: usize,
+    },
+    Spectating {
+        nicks: [String; 2],
+    },
+    SpectatePhaseOne {
+        dora_ind: Tile,
+        east: usize,
+    },
+    ChatReceived {
+        scope: ChatScope,
+        nick: String,
+        message: ChatMessage,
+    },
+}
+
+// Optional features, negotiated in the handshake. Messages belonging to a
+// capability are not accepted from, or sent to, clients that didn't ask for
+// it.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    Accounts,
+    Bots,
+    Queue,
+    Matches,
+    Rematch,
+    Spectate,
+    PrivateRooms,
+    Chat,
+    Resume,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 9] = [
+        Capability::Accounts,
+        Capability::Bots,
+        Capability::Queue,
+        Capability::Matches,
+        Capability::Rematch,
+        Capability::Spectate,
+        Capability::PrivateRooms,
+        Capability::Chat,
+        Capability::Resume,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        serde_json::from_value(serde_json::Value::String(name.to_owned())).ok()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    // protocol
+    UnsupportedVersion,
+    UnsupportedMessage,
+    InvalidMessage,
+    UnrecognizedMessage,
+    // lobby
+    AlreadyJoined,
+    NotJoined,
+    WrongKey,
+    InvalidRules,
+    NickTaken,
+    InvalidNick,
+    WrongToken,
+    InvalidChat,
+    ChatRateLimited,
+    UnknownStrategy,
+    // room
+    GameNotStarted,
+    GameFinished,
+    GameNotFinished,
+    AlreadySpectating,
+    WrongPassword,
+    NotInvited,
+    // anything else
+    Internal,
+}
+
+impl Msg {
+    // The capability needed to send or receive this message, if any.
+    pub fn capability(&self) -> Option<Capability> {
+        match self {
+            Msg::Replay { msg } => msg.capability(),
+            Msg::Register { .. } | Msg::Login { .. } | Msg::LoggedIn { .. } => {
+                Some(Capability::Accounts)
+            }
+            Msg::NewBotGame { .. } => Some(Capability::Bots),
+            Msg::QueueForMatch { .. } | Msg::LeaveQueue | Msg::Queued => Some(Capability::Queue),
+            Msg::MatchStatus { .. } | Msg::MatchEnd { .. } => Some(Capability::Matches),
+            Msg::RequestRematch | Msg::RematchOffered { .. } => Some(Capability::Rematch),
+            Msg::Spectate { .. } | Msg::Spectating { .. } | Msg::SpectatePhaseOne { .. } => {
+                Some(Capability::Spectate)
+            }
+            Msg::NewGame {
+                private: Some(_), ..
+            }
+            | Msg::Join {
+                password: Some(_), ..
+            } => Some(Capability::PrivateRooms),
+            Msg::Chat { .. } | Msg::ChatReceived { .. } => Some(Capability::Chat),
+            Msg::Rejoin {
+                last_seen_seq: Some(_),
+                ..
+            }
+            | Msg::Snapshot { .. } => Some(Capability::Resume),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatScope {
+    Lobby,
+    Room,
+}
+
+// Either free text, or one of the canned phrases (translated by the client):
+//     {"text": "..."}
+//     {"phrase": "good_game"}
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum ChatMessage {
+    Text(String),
+    Phrase(Phrase),
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Phrase {
+    Hello,
+    GoodLuck,
+    Thinking,
+    NiceHand,
+    GoodGame,
+    Thanks,
+}
+
+// A message as sent over the wire. Clients can add an id to a request, it's
+// echoed back on the direct replies. Everything sent by the server is
+// numbered, so that clients can detect gaps. Room events additionally carry
+// their number in the room, to be used for Rejoin:
+//
+//     {"type": "get_games", "id": 7}
+//     {"type": "games", "games": [...], "id": 7, "seq": 12}
+//     {"type": "discarded", "player": 1, "tile": "M1", "seq": 13, "event": 40}
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct Envelope {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<usize>,
+    #[serde(flatten)]
+    pub msg: Msg,
+}
+
+// A message for a user, with its number if it's a room event (that is, the
+// number in the events sent to this player, for the envelope).
+pub type Outgoing = (usize, Option<usize>, Msg);
+
+// A room not listed in the lobby, joinable only with the key
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Default)]
+pub struct PrivateRoom {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    // if not empty, only these nicks can join
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub invited: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct ScoreDetails {
+    pub fu: FuBreakdown,
+    pub yaku: Vec<(Yaku, usize)>,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Copy, Clone)]
@@ -88,8 +370,18 @@ pub enum MoveType {
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 pub enum PGame {
-    Player { nick: String, key: String },
-    Game { nicks: [String; 2] },
+    Player {
+        nick: String,
+        key: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        rating: Option<isize>,
+    },
+    Game {
+        nicks: [String; 2],
+        key: String,
+        #[serde(default)]
+        ratings: [Option<isize>; 2],
+    },
 }
 
 #[cfg(test)]
@@ -101,6 +393,46 @@ mod test {
         assert_eq!(serde_json::from_str::<Msg>(text).unwrap(), msg);
     }
 
+    #[test]
+    fn test_envelope() {
+        let envelope: Envelope = serde_json::from_str(r#"{"type":"get_games","id":7}"#).unwrap();
+        assert_eq!(
+            envelope,
+            Envelope {
+                id: Some(7),
+                seq: None,
+                event: None,
+                msg: Msg::GetGames,
+            }
+        );
+        let envelope = Envelope {
+            id: Some(7),
+            seq: Some(12),
+            event: None,
+            msg: Msg::Discard { tile: Tile::M1 },
+        };
+        assert_eq!(
+            serde_json::to_string(&envelope).unwrap(),
+            r#"{"id":7,"seq":12,"type":"discard","tile":"M1"}"#
+        );
+        let event = Envelope {
+            id: None,
+            seq: Some(13),
+            event: Some(40),
+            msg: Msg::Draw,
+        };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"seq":13,"event":40,"type":"draw"}"#
+        );
+
+        // clients not knowing about envelopes can ignore them
+        let msg: Msg = serde_json::from_str(r#"{"seq":12,"type":"draw"}"#).unwrap();
+        assert_eq!(msg, Msg::Draw);
+        // and event numbers are not messages
+        assert!(serde_json::from_str::<Msg>(r#"{"type":"event","seq":1}"#).is_err());
+    }
+
     #[test]
     fn test_serialize_msg() {
         check(Msg::GetGames, r#"{"type":"get_games"}"#);
@@ -109,6 +441,7 @@ mod test {
                 games: vec![PGame::Player {
                     nick: String::from("bot"),
                     key: String::from("xxx"),
+                    rating: None,
                 }],
             },
             r#"{"type":"games","games":[{"type":"player","nick":"bot","key":"xxx"}]}"#,
@@ -117,12 +450,48 @@ mod test {
             Msg::Join {
                 nick: String::from("bot"),
                 key: String::from("xxx"),
+                password: None,
             },
             r#"{"type":"join","nick":"bot","key":"xxx"}"#,
         );
         check(
             Msg::Discard { tile: Tile::X1 },
             r#"{"type":"discard","tile":"X1"}"#,
-        )
+        );
+        check(
+            Msg::Hello {
+                protocol_version: 1,
+                client: String::from("bot"),
+                capabilities: vec![String::from("chat")],
+            },
+            r#"{"type":"hello","protocol_version":1,"client":"bot","capabilities":["chat"]}"#,
+        );
+        check(
+            Msg::Error {
+                code: ErrorCode::UnsupportedVersion,
+                message: String::from("?"),
+                request: None,
+            },
+            r#"{"type":"error","code":"unsupported_version","message":"?"}"#,
+        );
+        check(
+            Msg::Error {
+                code: ErrorCode::WrongKey,
+                message: String::from("wrong key"),
+                request: Some(Box::new(Msg::Rejoin {
+                    key: String::from("xxx"),
+                    last_seen_seq: None,
+                })),
+            },
+            r#"{"type":"error","code":"wrong_key","message":"wrong key","request":{"type":"rejoin","key":"xxx"}}"#,
+        );
+        check(
+            Msg::ChatReceived {
+                scope: ChatScope::Room,
+                nick: String::from("bot"),
+                message: ChatMessage::Phrase(Phrase::GoodGame),
+            },
+            r#"{"type":"chat_received","scope":"room","nick":"bot","message":{"phrase":"good_game"}}"#,
+        );
     }
 }
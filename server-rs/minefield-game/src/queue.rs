@@ -0,0 +1,152 @@
+use minefield_core::rules::Ruleset;
+
+use crate::rating::INITIAL_RATING;
+
+pub struct QueueEntry {
+    pub user_id: usize,
+    pub nick: String,
+    pub rules: Ruleset,
+    pub rating: Option<isize>,
+    // maximum rating difference to the opponent
+    pub rating_band: Option<isize>,
+    // beats spent in the queue
+    pub waited: usize,
+}
+
+impl QueueEntry {
+    fn accepts(&self, other: &QueueEntry) -> bool {
+        if self.rules != other.rules {
+            return false;
+        }
+        match self.rating_band {
+            Some(band) => (self.rating() - other.rating()).abs() <= band,
+            None => true,
+        }
+    }
+
+    fn rating(&self) -> isize {
+        self.rating.unwrap_or(INITIAL_RATING as isize)
+    }
+}
+
+// Users waiting for an opponent, in order of arrival.
+pub struct MatchQueue {
+    entries: Vec<QueueEntry>,
+    // after how many beats to give up and play against a bot
+    pub bot_delay: Option<usize>,
+}
+
+impl MatchQueue {
+    pub fn new(bot_delay: Option<usize>) -> Self {
+        MatchQueue {
+            entries: vec![],
+            bot_delay,
+        }
+    }
+
+    pub fn contains(&self, user_id: usize) -> bool {
+        self.entries.iter().any(|entry| entry.user_id == user_id)
+    }
+
+    // Adds a user to the queue. If there is a matching opponent already
+    // waiting, removes them and returns both (the opponent first).
+    pub fn push(&mut self, entry: QueueEntry) -> Option<(QueueEntry, QueueEntry)> {
+        let found = self
+            .entries
+            .iter()
+            .position(|other| other.accepts(&entry) && entry.accepts(other));
+        match found {
+            Some(i) => Some((self.entries.remove(i), entry)),
+            None => {
+                self.entries.push(entry);
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, user_id: usize) -> Option<QueueEntry> {
+        let i = self
+            .entries
+            .iter()
+            .position(|entry| entry.user_id == user_id)?;
+        Some(self.entries.remove(i))
+    }
+
+    // Returns users who waited too long.
+    pub fn beat(&mut self) -> Vec<QueueEntry> {
+        for entry in self.entries.iter_mut() {
+            entry.waited += 1;
+        }
+        match self.bot_delay {
+            Some(delay) => {
+                let (timed_out, entries) = self
+                    .entries
+                    .drain(..)
+                    .partition(|entry| entry.waited >= delay);
+                self.entries = entries;
+                timed_out
+            }
+            None => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(user_id: usize, rating: Option<isize>, rating_band: Option<isize>) -> QueueEntry {
+        QueueEntry {
+            user_id,
+            nick: format!("player{}", user_id),
+            rules: Ruleset::default(),
+            rating,
+            rating_band,
+            waited: 0,
+        }
+    }
+
+    #[test]
+    fn test_pair() {
+        let mut queue = MatchQueue::new(None);
+        assert!(queue.push(entry(1, None, None)).is_none());
+        let (a, b) = queue.push(entry(2, Some(1800), None)).unwrap();
+        assert_eq!((a.user_id, b.user_id), (1, 2));
+        assert!(!queue.contains(1));
+    }
+
+    #[test]
+    fn test_rating_band() {
+        let mut queue = MatchQueue::new(None);
+        assert!(queue.push(entry(1, Some(1800), Some(100))).is_none());
+        // too weak for 1
+        assert!(queue.push(entry(2, None, None)).is_none());
+        // too far from both
+        assert!(queue.push(entry(3, Some(1650), Some(100))).is_none());
+        let (a, b) = queue.push(entry(4, Some(1750), None)).unwrap();
+        assert_eq!((a.user_id, b.user_id), (1, 4));
+    }
+
+    #[test]
+    fn test_rules() {
+        let mut queue = MatchQueue::new(None);
+        let mut e = entry(1, None, None);
+        e.rules.hands = 2;
+        assert!(queue.push(e).is_none());
+        assert!(queue.push(entry(2, None, None)).is_none());
+        assert!(queue.remove(2).is_some());
+        assert!(queue.remove(2).is_none());
+    }
+
+    #[test]
+    fn test_bot_delay() {
+        let mut queue = MatchQueue::new(Some(2));
+        queue.push(entry(1, None, None));
+        assert!(queue.beat().is_empty());
+        queue.push(entry(2, Some(1800), Some(0)));
+        let timed_out = queue.beat();
+        assert_eq!(timed_out.len(), 1);
+        assert_eq!(timed_out[0].user_id, 1);
+        assert!(queue.contains(2));
+    }
+}
@@ -0,0 +1,45 @@
+use crate::record::GameResult;
+
+pub const INITIAL_RATING: f64 = 1500.0;
+
+// Maximum rating change for a single game
+const K: f64 = 32.0;
+
+// Score of player 0 (1 for a win, 0.5 for a draw, 0 for a loss). An
+// aborted game is lost by the player who caused it.
+pub fn score(result: &GameResult) -> f64 {
+    match result {
+        GameResult::Ron { player: 0, .. } => 1.0,
+        GameResult::Ron { .. } => 0.0,
+        GameResult::Draw => 0.5,
+        GameResult::Abort { culprit: 0, .. } => 0.0,
+        GameResult::Abort { .. } => 1.0,
+    }
+}
+
+// Elo update for a game with the given score of player 0.
+pub fn update(ratings: [f64; 2], score: f64) -> [f64; 2] {
+    let expected = 1.0 / (1.0 + 10f64.powf((ratings[1] - ratings[0]) / 400.0));
+    let change = K * (score - expected);
+    [ratings[0] + change, ratings[1] - change]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_update() {
+        assert_eq!(update([1500.0, 1500.0], 1.0), [1516.0, 1484.0]);
+        assert_eq!(update([1500.0, 1500.0], 0.5), [1500.0, 1500.0]);
+
+        // beating a weaker player gives less
+        let [a, b] = update([1700.0, 1300.0], 1.0);
+        assert!(a - 1700.0 < 4.0);
+        assert!((a - 1700.0) + (b - 1300.0) < 1e-9);
+
+        // draw against a stronger player gives something
+        let [a, _] = update([1300.0, 1700.0], 0.5);
+        assert!(a > 1300.0);
+    }
+}
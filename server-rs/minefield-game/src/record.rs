@@ -0,0 +1,302 @@
+// Records of finished games, for reviewing and sharing.
+//
+// A record serializes to JSON as follows:
+//
+//     {
+//       "rules": {...},              // Ruleset
+//       "east": 0,                   // index of the east player
+//       "dora_ind": "M1",
+//       "uradora_ind": "P3",
+//       "seed": 1234,                // the tiles were dealt from (optional)
+//       "players": [
+//         {
+//           "nick": "Akagi",
+//           "tiles": ["M1", ...],    // initial pool, in the order dealt
+//           "hand": ["M2", ...],     // chosen hand (empty if none)
+//           "discards": ["S4", ...], // in order
+//           "bot_seed": 91011        // if played by a bot (optional)
+//         },
+//         ...
+//       ],
+//       "result": {"type": "ron", "player": 1, "hand": [...], "tile": "P5",
+//                  "yaku": ["riichi", ...], "dora": 1, "uradora_ind": "P3",
+//                  "limit": 1, "points": 8000, "details": {...}}
+//              or {"type": "draw"}
+//              or {"type": "abort", "culprit": 0, "description": "..."}
+//              or null (game not finished)
+//     }
+//
+// The east player discards first, so the order of all discards can be
+// recovered from the players' discards (see `GameRecord::discards`).
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use minefield_core::rules::Ruleset;
+use minefield_core::tiles::{Suit, Tile};
+use minefield_core::yaku::Yaku;
+
+use crate::protocol::{Msg, ScoreDetails};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct GameRecord {
+    pub rules: Ruleset,
+    pub east: usize,
+    pub dora_ind: Tile,
+    pub uradora_ind: Tile,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u64>,
+    pub players: [PlayerRecord; 2],
+    pub result: Option<GameResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct PlayerRecord {
+    pub nick: String,
+    pub tiles: Vec<Tile>,
+    pub hand: Vec<Tile>,
+    pub discards: Vec<Tile>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bot_seed: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum GameResult {
+    Ron {
+        player: usize,
+        hand: Vec<Tile>,
+        tile: Tile,
+        yaku: Vec<Yaku>,
+        dora: usize,
+        uradora_ind: Tile,
+        limit: usize,
+        points: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        details: Option<ScoreDetails>,
+    },
+    Draw,
+    Abort {
+        culprit: usize,
+        description: String,
+    },
+}
+
+impl GameResult {
+    pub fn from_msg(msg: &Msg) -> Option<Self> {
+        match msg.clone() {
+            Msg::Ron {
+                player,
+                hand,
+                tile,
+                yaku,
+                dora,
+                uradora_ind,
+                limit,
+                points,
+                details,
+            } => Some(GameResult::Ron {
+                player,
+                hand,
+                tile,
+                yaku,
+                dora,
+                uradora_ind,
+                limit,
+                points,
+                details,
+            }),
+            Msg::Draw => Some(GameResult::Draw),
+            Msg::Abort {
+                culprit,
+                description,
+            } => Some(GameResult::Abort {
+                culprit,
+                description,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl GameRecord {
+    // All discards, in order, as (player, tile).
+    pub fn discards(&self) -> Vec<(usize, Tile)> {
+        let order = [self.east, 1 - self.east];
+        let mut result = vec![];
+        for turn in 0.. {
+            let mut any = false;
+            for &i in order.iter() {
+                if let Some(tile) = self.players[i].discards.get(turn) {
+                    result.push((i, *tile));
+                    any = true;
+                }
+            }
+            if !any {
+                break;
+            }
+        }
+        result
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    // Log in the format used by tenhou.net/6 (and viewers based on it).
+    //
+    // There are no draws in Minefield, so every discard is written as a
+    // tile drawn and immediately discarded. East is seat 0, seats 2 and 3
+    // are empty.
+    pub fn to_tenhou(&self) -> Value {
+        let seats = [self.east, 1 - self.east];
+
+        let mut deltas = [0isize; 4];
+        let mut uradora = vec![];
+        let result = match self.result {
+            Some(GameResult::Ron {
+                player,
+                ref yaku,
+                dora,
+                uradora_ind,
+                limit,
+                points,
+                ref details,
+                ..
+            }) => {
+                let winner = seat(seats, player);
+                let loser = seat(seats, 1 - player);
+                deltas[winner] = points as isize;
+                deltas[loser] = -(points as isize);
+                if self.rules.uradora {
+                    uradora.push(tenhou_tile(uradora_ind));
+                }
+
+                let fan: usize = yaku.iter().map(|y| y.fan()).sum::<usize>() + dora;
+                let mut info = vec![
+                    json!(winner),
+                    json!(loser),
+                    json!(winner),
+                    json!(score_text(limit, fan, details.as_ref(), points)),
+                ];
+                for y in yaku.iter() {
+                    info.push(json!(yaku_text(*y)));
+                }
+                if dora > 0 {
+                    info.push(json!(format!("ドラ({}飜)", dora)));
+                }
+                json!(["和了", deltas, info])
+            }
+            _ => json!(["流局", deltas]),
+        };
+
+        let mut round = vec![
+            json!([0, 0, 0]),
+            json!([0, 0, 0, 0]),
+            json!([tenhou_tile(self.dora_ind)]),
+            json!(uradora),
+        ];
+        for &i in seats.iter() {
+            let player = &self.players[i];
+            let mut hand: Vec<usize> = player.hand.iter().map(|t| tenhou_tile(*t)).collect();
+            hand.sort_unstable();
+            let draws: Vec<usize> = player.discards.iter().map(|t| tenhou_tile(*t)).collect();
+            let discards: Vec<usize> = player.discards.iter().map(|_| 60).collect();
+            round.push(json!(hand));
+            round.push(json!(draws));
+            round.push(json!(discards));
+        }
+        for _ in 0..6 {
+            round.push(json!([]));
+        }
+        round.push(result);
+
+        json!({
+            "title": ["Minefield", ""],
+            "name": [
+                self.players[seats[0]].nick,
+                self.players[seats[1]].nick,
+                "",
+                "",
+            ],
+            "rule": {"disp": "Minefield", "aka": 0},
+            "log": [round],
+        })
+    }
+}
+
+fn seat(seats: [usize; 2], player: usize) -> usize {
+    if seats[0] == player {
+        0
+    } else {
+        1
+    }
+}
+
+fn tenhou_tile(tile: Tile) -> usize {
+    let suit = match tile.suit() {
+        Suit::Man => 1,
+        Suit::Pin => 2,
+        Suit::Sou => 3,
+        Suit::Honor => 4,
+    };
+    suit * 10 + tile.number()
+}
+
+fn score_text(limit: usize, fan: usize, details: Option<&ScoreDetails>, points: usize) -> String {
+    let name = match limit {
+        0 => {
+            let fu = details.map_or(0, |details| details.fu.total);
+            return format!("{}符{}飜{}点", fu, fan, points);
+        }
+        1 => "満貫",
+        2 => "跳満",
+        3 => "倍満",
+        4 => "三倍満",
+        _ => "役満",
+    };
+    format!("{}{}点", name, points)
+}
+
+fn yaku_text(yaku: Yaku) -> String {
+    use Yaku::*;
+    let name = match yaku {
+        Riichi => "立直",
+        Ippatsu => "一発",
+        Hotei => "河底撈魚",
+        Pinfu => "平和",
+        Iipeiko => "一盃口",
+        Tanyao => "断幺九",
+        Wind => "役牌 自風牌",
+        Haku => "役牌 白",
+        Hatsu => "役牌 發",
+        Chun => "役牌 中",
+        Sanshokudojun => "三色同順",
+        Sanshokudoko => "三色同刻",
+        Itsuu => "一気通貫",
+        Chitoitsu => "七対子",
+        Chanta => "混全帯幺九",
+        Honroto => "混老頭",
+        Toitoi => "対々和",
+        Sananko => "三暗刻",
+        Shosangen => "小三元",
+        Ryanpeiko => "二盃口",
+        Junchan => "純全帯幺九",
+        Honitsu => "混一色",
+        Chinitsu => "清一色",
+        Daisangen => "大三元",
+        Kokushi => "国士無双",
+        Suuanko => "四暗刻",
+        Suushi => "四喜和",
+        Chinroto => "清老頭",
+        Tsuuiiso => "字一色",
+        Ryuuiiso => "緑一色",
+        Chuuren => "九蓮宝燈",
+    };
+    if yaku.fan() >= 13 {
+        format!("{}(役満)", name)
+    } else {
+        format!("{}({}飜)", name, yaku.fan())
+    }
+}
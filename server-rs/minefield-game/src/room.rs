@@ -1,8 +1,15 @@
 use failure::{Error, Fail};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
+use minefield_core::rules::Ruleset;
+use minefield_core::seed::Seeder;
+
+use crate::bot_player::{BotPlayer, BotResult, BotTask, BOT_NICK};
 use crate::game::Game;
-use crate::protocol::{Msg, PGame};
+use crate::game_match::Match;
+use crate::protocol::{ChatMessage, ChatScope, ErrorCode, Msg, Outgoing, PGame, PrivateRoom};
+use crate::record::GameRecord;
 
 #[derive(Debug, Fail)]
 pub enum RoomError {
@@ -12,31 +19,129 @@ pub enum RoomError {
     GameNotStarted,
     #[fail(display = "game finished")]
     GameFinished,
+    #[fail(display = "game not finished")]
+    GameNotFinished,
+    #[fail(display = "already spectating")]
+    AlreadySpectating,
+    #[fail(display = "wrong password")]
+    WrongPassword,
+    #[fail(display = "not invited")]
+    NotInvited,
+}
+
+impl RoomError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            RoomError::AlreadyJoined => ErrorCode::AlreadyJoined,
+            RoomError::GameNotStarted => ErrorCode::GameNotStarted,
+            RoomError::GameFinished => ErrorCode::GameFinished,
+            RoomError::GameNotFinished => ErrorCode::GameNotFinished,
+            RoomError::AlreadySpectating => ErrorCode::AlreadySpectating,
+            RoomError::WrongPassword => ErrorCode::WrongPassword,
+            RoomError::NotInvited => ErrorCode::NotInvited,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Room {
     game: Option<Game>,
+    #[serde(default)]
+    rules: Ruleset,
+    // everything random in the room (games, bots) comes from here, except
+    // for the keys, which are secret
+    #[serde(default = "Seeder::from_entropy")]
+    seeder: Seeder,
+    // bots think without time limits (see `BotPlayer::set_reproducible`)
+    #[serde(default)]
+    reproducible: bool,
+    #[serde(default)]
+    private: Option<Access>,
+    #[serde(default)]
+    game_match: Option<Match>,
+    // the finished game has been saved to history
+    #[serde(default)]
+    game_recorded: bool,
+    // players who asked for a rematch
+    #[serde(default)]
+    rematch: [bool; 2],
+    // players played by the server
+    #[serde(default)]
+    bots: [Option<BotPlayer>; 2],
+    // accounts of logged in players, and their ratings when they joined
+    #[serde(default)]
+    player_ids: [Option<usize>; 2],
+    #[serde(default)]
+    ratings: [Option<isize>; 2],
     #[serde(skip)]
     user_ids: [Option<usize>; 2],
+    #[serde(skip)]
+    spectators: Vec<usize>,
     nicks: [String; 2],
     pub room_key: String,
     pub player_keys: [String; 2],
     messages: [Vec<Msg>; 2],
+    // number of earlier messages no longer kept (from previous hands and
+    // games), so that event numbers keep growing
+    #[serde(default)]
+    history_start: [usize; 2],
+    // spectator-safe view, for replaying
+    #[serde(default)]
+    spectator_messages: Vec<Msg>,
+}
+
+// Who can join a private room. Only a hash of the password is kept, so that
+// it doesn't sit in the database in plain text.
+#[derive(Serialize, Deserialize, Default)]
+struct Access {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    invited: Vec<String>,
+}
+
+// Salted with the room key, so that the same password doesn't look the same
+// in different rooms.
+pub(crate) fn hash_password(room_key: &str, password: &str) -> String {
+    let salted = format!("{}:{}", room_key, password);
+    format!("{:x}", Sha256::digest(salted.as_bytes()))
 }
 
 impl Room {
     pub fn new(user_id: usize, nick: String) -> Self {
+        Self::with_rules(user_id, nick, Ruleset::default())
+    }
+
+    pub fn with_rules(user_id: usize, nick: String, rules: Ruleset) -> Self {
+        Self::with_seed(user_id, nick, rules, rand::random())
+    }
+
+    pub fn with_seed(user_id: usize, nick: String, rules: Ruleset, seed: u64) -> Self {
         Room {
             game: None,
+            rules,
+            seeder: Seeder::new(seed),
+            reproducible: false,
+            private: None,
+            game_match: None,
+            game_recorded: false,
+            rematch: [false, false],
+            bots: [None, None],
+            player_ids: [None, None],
+            ratings: [None, None],
             user_ids: [Some(user_id), None],
             nicks: [nick, "".to_owned()],
             room_key: Self::gen_key(),
             player_keys: [Self::gen_key(), Self::gen_key()],
             messages: [vec![], vec![]],
+            history_start: [0, 0],
+            spectators: vec![],
+            spectator_messages: vec![],
         }
     }
 
+    // Not seeded: keys let anyone in, and have to stay secret even with a
+    // known seed.
     fn gen_key() -> String {
         use rand::seq::IteratorRandom;
 
@@ -50,26 +155,101 @@ impl Room {
 
     pub fn describe(&self) -> Option<PGame> {
         match self.game.as_ref() {
-            Some(game) if game.finished => None,
+            Some(_) if self.game_finished() => None,
             Some(_) => Some(PGame::Game {
                 nicks: self.nicks.clone(),
+                key: self.room_key.clone(),
+                ratings: self.ratings,
             }),
             None if self.user_ids[0].is_some() => Some(PGame::Player {
                 nick: self.nicks[0].clone(),
                 key: self.room_key.clone(),
+                rating: self.ratings[0],
             }),
             None => None,
         }
     }
 
-    pub fn beat(&mut self) -> Vec<(usize, Msg)> {
+    pub fn beat(&mut self) -> Vec<Outgoing> {
+        let (mut messages, tasks) = self.beat_deferred();
+        for task in tasks.into_iter() {
+            messages.append(&mut self.bot_moved(task.run()));
+        }
+        messages
+    }
+
+    // Like `beat`, but leaves choosing the bots' moves to the caller.
+    pub fn beat_deferred(&mut self) -> (Vec<Outgoing>, Vec<BotTask>) {
+        let messages = self.beat_game();
+        let tasks = self.bots.iter_mut().flatten().filter_map(BotPlayer::beat);
+        (messages, tasks.collect())
+    }
+
+    pub fn bot_moved(&mut self, result: BotResult) -> Vec<Outgoing> {
+        let i = result.seat;
+        match self.bots[i].as_mut().and_then(|bot| bot.finish(result)) {
+            Some(msg) => self.bot_message(i, msg),
+            None => vec![],
+        }
+    }
+
+    fn beat_game(&mut self) -> Vec<Outgoing> {
         match self.game.as_mut() {
             Some(game) if !game.finished => {
                 game.beat();
                 self.messages()
             }
-            _ => vec![],
+            Some(_) => {
+                // Break between hands of a match
+                let next_hand = self.game_match.as_mut().is_some_and(Match::beat);
+                if next_hand {
+                    self.start_hand()
+                } else {
+                    vec![]
+                }
+            }
+            None => vec![],
+        }
+    }
+
+    // Record of the current (or last) game
+    pub fn record(&self) -> Option<GameRecord> {
+        self.game.as_ref().map(|game| self.game_record(game))
+    }
+
+    // Record of a game that just finished, returned only once
+    pub fn take_finished_record(&mut self) -> Option<GameRecord> {
+        match self.game {
+            Some(ref game) if game.finished && !self.game_recorded => {
+                let record = self.game_record(game);
+                self.game_recorded = true;
+                Some(record)
+            }
+            _ => None,
+        }
+    }
+
+    // With the seeds needed to replay the game, bots included
+    fn game_record(&self, game: &Game) -> GameRecord {
+        let mut record = game.record(&self.nicks);
+        for i in 0..2 {
+            record.players[i].bot_seed = self.bots[i].as_ref().and_then(BotPlayer::bot_seed);
         }
+        record
+    }
+
+    pub fn set_account(&mut self, user_id: usize, player_id: usize, rating: isize) {
+        let i = self.find_player(user_id).unwrap();
+        self.player_ids[i] = Some(player_id);
+        self.ratings[i] = Some(rating);
+    }
+
+    pub fn player_ids(&self) -> [Option<usize>; 2] {
+        self.player_ids
+    }
+
+    pub fn set_ratings(&mut self, ratings: [isize; 2]) {
+        self.ratings = [Some(ratings[0]), Some(ratings[1])];
     }
 
     pub fn started(&self) -> bool {
@@ -77,14 +257,57 @@ impl Room {
     }
 
     pub fn finished(&self) -> bool {
-        let game_finished = match self.game.as_ref() {
-            Some(game) => game.finished,
-            None => true,
-        };
-        game_finished && self.user_ids[0].is_none() && self.user_ids[1].is_none()
+        self.game_finished() && self.user_ids[0].is_none() && self.user_ids[1].is_none()
+    }
+
+    // The game, and the whole match (if any) is finished
+    fn game_finished(&self) -> bool {
+        match (self.game.as_ref(), self.game_match.as_ref()) {
+            (Some(_), Some(game_match)) => game_match.finished,
+            (Some(game), None) => game.finished,
+            (None, _) => true,
+        }
     }
 
-    pub fn connect(&mut self, user_id: usize, nick: String) -> Result<Vec<(usize, Msg)>, Error> {
+    pub fn seed(&self) -> u64 {
+        self.seeder.seed()
+    }
+
+    pub fn set_reproducible(&mut self, reproducible: bool) {
+        self.reproducible = reproducible;
+    }
+
+    pub fn set_private(&mut self, private: Option<PrivateRoom>) {
+        self.private = private.map(|private| Access {
+            password_hash: private
+                .password
+                .map(|password| hash_password(&self.room_key, &password)),
+            invited: private.invited,
+        });
+    }
+
+    pub fn private(&self) -> bool {
+        self.private.is_some()
+    }
+
+    // Invites are by nick, so the caller must make sure the nick is really
+    // theirs (registered nicks only for the logged in account).
+    pub fn check_access(&self, nick: &str, password: Option<&str>) -> Result<(), Error> {
+        if let Some(ref private) = self.private {
+            if let Some(ref password_hash) = private.password_hash {
+                let password = password.map(|password| hash_password(&self.room_key, password));
+                if password.as_ref() != Some(password_hash) {
+                    return Err(RoomError::WrongPassword.into());
+                }
+            }
+            if !private.invited.is_empty() && !private.invited.iter().any(|n| n == nick) {
+                return Err(RoomError::NotInvited.into());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn connect(&mut self, user_id: usize, nick: String) -> Result<Vec<Outgoing>, Error> {
         if self.user_ids[1].is_some() || self.game.is_some() {
             return Err(RoomError::AlreadyJoined.into());
         }
@@ -94,65 +317,193 @@ impl Room {
 
         assert!(self.user_ids[0] != self.user_ids[1]);
 
+        Ok(self.start())
+    }
+
+    pub fn connect_bot(&mut self, strategy: &str) -> Result<Vec<Outgoing>, Error> {
+        if self.user_ids[1].is_some() || self.game.is_some() {
+            return Err(RoomError::AlreadyJoined.into());
+        }
+
+        let mut bot = BotPlayer::new(1, strategy, self.seeder.next_seed());
+        bot.set_reproducible(self.reproducible);
+        self.bots[1] = Some(bot);
+        self.nicks[1] = BOT_NICK.to_owned();
+        Ok(self.start())
+    }
+
+    fn start(&mut self) -> Vec<Outgoing> {
         let mut messages = vec![];
         for i in 0..2 {
-            if let Some(user_id) = self.user_ids[i] {
-                let msg = Msg::Room {
-                    you: i,
-                    nicks: self.nicks.clone(),
-                    key: self.player_keys[i].clone(),
-                };
-                self.messages[i].push(msg.clone());
-                messages.push((user_id, msg));
-            }
+            messages.push((i, self.room_msg(i)));
         }
+        let mut messages = self.send(messages);
 
-        let mut game = Game::new(&mut rand::thread_rng());
+        if self.rules.hands > 1 {
+            let east = rand::Rng::gen_range(&mut self.seeder.rng(), 0, 2);
+            self.game_match = Some(Match::new(east));
+        }
+
+        messages.append(&mut self.start_hand());
+        messages
+    }
+
+    fn room_msg(&self, i: usize) -> Msg {
+        Msg::Room {
+            you: i,
+            nicks: self.nicks.clone(),
+            key: self.player_keys[i].clone(),
+        }
+    }
+
+    // Forget the events so far, only the current hand is kept for replaying.
+    fn trim_history(&mut self) {
+        for i in 0..2 {
+            self.history_start[i] += self.messages[i].len();
+            self.messages[i].clear();
+        }
+    }
+
+    fn start_hand(&mut self) -> Vec<Outgoing> {
+        if self.game.is_some() {
+            self.trim_history();
+        }
+        let seed = self.seeder.next_seed();
+        let mut messages = vec![];
+        let mut game = match self.game_match {
+            Some(ref game_match) => {
+                let msg = game_match.status_msg(&self.rules);
+                messages.push((0, msg.clone()));
+                messages.push((1, msg));
+                Game::from_seed(seed, Some(game_match.east()), self.rules.clone())
+            }
+            None => Game::from_seed(seed, None, self.rules.clone()),
+        };
         game.on_start();
+        messages.append(&mut game.messages());
         self.game = Some(game);
-
-        messages.append(&mut self.messages());
-        Ok(messages)
+        self.game_recorded = false;
+        self.send(messages)
     }
 
-    pub fn rejoin(&mut self, user_id: usize, i: usize) -> Result<Vec<(usize, Msg)>, Error> {
-        if self.user_ids[i].is_some() {
+    // Replays the events after `last_seen_seq`, or all the kept events if
+    // not given. If some of the missed events are gone (or the client claims
+    // to have seen events that never happened), sends a snapshot instead.
+    pub fn rejoin(
+        &mut self,
+        user_id: usize,
+        i: usize,
+        last_seen_seq: Option<usize>,
+    ) -> Result<Vec<Outgoing>, Error> {
+        if self.user_ids[i].is_some() || self.bots[i].is_some() {
             return Err(RoomError::AlreadyJoined.into());
         }
 
         self.user_ids[i] = Some(user_id);
         assert!(self.user_ids[0] != self.user_ids[1]);
 
-        let mut replayed: Vec<(usize, Msg)> = self.messages[i]
+        let start = self.history_start[i];
+        let end = start + self.messages[i].len();
+        let mut replayed = vec![];
+        let skip = match last_seen_seq {
+            None => {
+                // Earlier hands are gone, but the client still needs to
+                // know where it is.
+                if start > 0 {
+                    let msg = Box::new(self.room_msg(i));
+                    replayed.push((user_id, None, Msg::Replay { msg }));
+                }
+                0
+            }
+            Some(seen) if start <= seen && seen <= end => seen - start,
+            Some(_) => return Ok(self.snapshot(user_id, i)),
+        };
+        for (k, msg) in self.messages[i].iter().enumerate().skip(skip) {
+            let msg = Msg::Replay {
+                msg: Box::new(msg.clone()),
+            };
+            replayed.push((user_id, Some(start + k + 1), msg));
+        }
+
+        if let Some(ref game) = self.game {
+            if !game.finished {
+                if let Some(msg) = game.rejoin_msg(i) {
+                    replayed.push((user_id, None, Msg::Replay { msg: Box::new(msg) }))
+                }
+            }
+        }
+
+        Ok(replayed)
+    }
+
+    fn snapshot(&self, user_id: usize, i: usize) -> Vec<Outgoing> {
+        let mut messages = vec![(
+            user_id,
+            None,
+            Msg::Replay {
+                msg: Box::new(self.room_msg(i)),
+            },
+        )];
+        if let Some(ref game_match) = self.game_match {
+            let msg = Box::new(game_match.status_msg(&self.rules));
+            messages.push((user_id, None, Msg::Replay { msg }));
+        }
+        if let Some(ref game) = self.game {
+            let seq = self.history_start[i] + self.messages[i].len();
+            messages.push((user_id, Some(seq), game.snapshot(i)));
+            if !game.finished {
+                if let Some(msg) = game.rejoin_msg(i) {
+                    messages.push((user_id, None, Msg::Replay { msg: Box::new(msg) }));
+                }
+            }
+        }
+        messages
+    }
+
+    pub fn spectate(&mut self, user_id: usize) -> Result<Vec<Outgoing>, Error> {
+        if self.game.is_none() {
+            return Err(RoomError::GameNotStarted.into());
+        }
+        if self.spectators.contains(&user_id) || self.find_player(user_id).is_some() {
+            return Err(RoomError::AlreadySpectating.into());
+        }
+
+        self.spectators.push(user_id);
+        Ok(self
+            .spectator_messages
             .iter()
             .map(|msg| {
                 (
                     user_id,
+                    None,
                     Msg::Replay {
                         msg: Box::new(msg.clone()),
                     },
                 )
             })
-            .collect();
-
-        if let Some(ref game) = self.game {
-            if !game.finished {
-                if let Some(msg) = game.rejoin_msg(i) {
-                    replayed.push((user_id, Msg::Replay { msg: Box::new(msg) }))
-                }
-            }
-        }
+            .collect())
+    }
 
-        Ok(replayed)
+    pub fn stop_spectating(&mut self, user_id: usize) {
+        self.spectators.retain(|id| *id != user_id);
     }
 
     pub fn disconnect(&mut self, user_id: usize) {
         let i = self.find_player(user_id).unwrap();
         self.user_ids[i] = None;
+        // a rematch needs both players still there
+        self.rematch[i] = false;
     }
 
-    pub fn on_message(&mut self, user_id: usize, msg: Msg) -> Result<Vec<(usize, Msg)>, Error> {
+    pub fn on_message(&mut self, user_id: usize, msg: Msg) -> Result<Vec<Outgoing>, Error> {
         let i = self.find_player(user_id).unwrap();
+        self.on_player_message(i, msg)
+    }
+
+    fn on_player_message(&mut self, i: usize, msg: Msg) -> Result<Vec<Outgoing>, Error> {
+        if let Msg::RequestRematch = msg {
+            return self.request_rematch(i);
+        }
 
         let game = self.game.as_mut().ok_or(RoomError::GameNotStarted)?;
         if game.finished {
@@ -163,6 +514,57 @@ impl Room {
         Ok(self.messages())
     }
 
+    // Chat is kept with the other messages, so that it's replayed on rejoin.
+    pub fn chat(&mut self, user_id: usize, message: ChatMessage) -> Result<Vec<Outgoing>, Error> {
+        if self.game.is_none() {
+            return Err(RoomError::GameNotStarted.into());
+        }
+        let i = self.find_player(user_id).unwrap();
+        let msg = Msg::ChatReceived {
+            scope: ChatScope::Room,
+            nick: self.nicks[i].clone(),
+            message,
+        };
+        Ok(self.send(vec![(0, msg.clone()), (1, msg)]))
+    }
+
+    fn request_rematch(&mut self, i: usize) -> Result<Vec<Outgoing>, Error> {
+        if self.game.is_none() {
+            return Err(RoomError::GameNotStarted.into());
+        }
+        if !self.game_finished() {
+            return Err(RoomError::GameNotFinished.into());
+        }
+
+        self.rematch[i] = true;
+        let mut messages = self.send(vec![
+            (0, Msg::RematchOffered { player: i }),
+            (1, Msg::RematchOffered { player: i }),
+        ]);
+        let connected = |i: usize| self.user_ids[i].is_some() || self.bots[i].is_some();
+        if !(self.rematch[0] && self.rematch[1] && connected(0) && connected(1)) {
+            return Ok(messages);
+        }
+
+        // Both agreed: start over with swapped seats. Player keys move
+        // together with the players, so rejoining still works.
+        self.rematch = [false, false];
+        self.trim_history();
+        self.history_start.swap(0, 1);
+        self.user_ids.swap(0, 1);
+        self.nicks.swap(0, 1);
+        self.player_keys.swap(0, 1);
+        self.bots.swap(0, 1);
+        self.player_ids.swap(0, 1);
+        self.ratings.swap(0, 1);
+        self.spectator_messages = vec![];
+        self.game = None;
+        self.game_match = None;
+
+        messages.append(&mut self.start());
+        Ok(messages)
+    }
+
     fn find_player(&self, user_id: usize) -> Option<usize> {
         if self.user_ids[0] == Some(user_id) {
             Some(0)
@@ -173,25 +575,93 @@ impl Room {
         }
     }
 
-    fn messages(&mut self) -> Vec<(usize, Msg)> {
-        let mut result = vec![];
+    fn messages(&mut self) -> Vec<Outgoing> {
         let game = self.game.as_mut().unwrap();
-        for (i, msg) in game.messages().into_iter() {
+        let mut messages = game.messages();
+
+        if let Some(ref mut game_match) = self.game_match {
+            let result = messages.iter().find_map(|(i, msg)| match msg {
+                Msg::Ron { .. } | Msg::Draw | Msg::Abort { .. } if *i == 0 => Some(msg.clone()),
+                _ => None,
+            });
+            if let Some(result) = result {
+                for msg in game_match.on_result(&result, &self.rules) {
+                    messages.push((0, msg.clone()));
+                    messages.push((1, msg));
+                }
+            }
+        }
+
+        self.send(messages)
+    }
+
+    fn send(&mut self, messages: Vec<(usize, Msg)>) -> Vec<Outgoing> {
+        let mut result = vec![];
+        let mut bot_replies = vec![];
+        for (i, msg) in messages.into_iter() {
+            // Public events are also sent to players, use player 0's copy
+            if i == 0 {
+                if let Some(view) = spectator_view(&msg) {
+                    self.spectator_messages.push(view.clone());
+                    for user_id in self.spectators.iter() {
+                        result.push((*user_id, None, view.clone()));
+                    }
+                }
+            }
+
             // Add messsage for replaying
             self.messages[i].push(msg.clone());
+            let seq = self.history_start[i] + self.messages[i].len();
 
             // Return if there's user connected
             if let Some(user_id) = self.user_ids[i] {
-                result.push((user_id, msg));
+                result.push((user_id, Some(seq), msg));
+            } else if let Some(ref mut bot) = self.bots[i] {
+                if let Some(reply) = bot.on_message(&msg, &self.rules) {
+                    bot_replies.push((i, reply));
+                }
             }
         }
+        for (i, msg) in bot_replies.into_iter() {
+            result.append(&mut self.bot_message(i, msg));
+        }
         result
     }
+
+    fn bot_message(&mut self, i: usize, msg: Msg) -> Vec<Outgoing> {
+        // The bot might be late (game aborted in the meantime), just ignore
+        // the errors
+        self.on_player_message(i, msg).unwrap_or_default()
+    }
+}
+
+// What spectators see of a message: no hidden tiles, and no player keys.
+fn spectator_view(msg: &Msg) -> Option<Msg> {
+    match msg {
+        Msg::Room { nicks, .. } => Some(Msg::Spectating {
+            nicks: nicks.clone(),
+        }),
+        Msg::PhaseOne { dora_ind, east, .. } => Some(Msg::SpectatePhaseOne {
+            dora_ind: *dora_ind,
+            east: *east,
+        }),
+        Msg::PhaseTwo
+        | Msg::Discarded { .. }
+        | Msg::Ron { .. }
+        | Msg::Draw
+        | Msg::Abort { .. }
+        | Msg::MatchStatus { .. }
+        | Msg::MatchEnd { .. }
+        | Msg::RematchOffered { .. }
+        | Msg::ChatReceived { .. } => Some(msg.clone()),
+        _ => None,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use minefield_core::strategy::DEFAULT_STRATEGY;
     use minefield_core::tiles::Tile;
 
     #[test]
@@ -203,6 +673,7 @@ mod tests {
             Some(PGame::Player {
                 nick: "Akagi".to_owned(),
                 key: room.room_key.clone(),
+                rating: None,
             })
         );
     }
@@ -210,12 +681,14 @@ mod tests {
     #[test]
     fn test_join() {
         let mut room = Room::new(33, "Akagi".to_owned());
-        let messages = room.connect(55, "Washizu".to_owned()).unwrap();
+        let messages = events(room.connect(55, "Washizu".to_owned()).unwrap());
 
         assert_eq!(
             room.describe(),
             Some(PGame::Game {
                 nicks: ["Akagi".to_owned(), "Washizu".to_owned()],
+                key: room.room_key.clone(),
+                ratings: [None, None],
             })
         );
 
@@ -229,6 +702,50 @@ mod tests {
         assert!(matches!(messages[5], (55, Msg::StartMove { .. })));
     }
 
+    #[test]
+    fn test_seed() {
+        let start = |seed| {
+            let mut room = Room::with_seed(33, "Akagi".to_owned(), Ruleset::default(), seed);
+            let messages = room.connect(55, "Washizu".to_owned()).unwrap();
+            (room, messages)
+        };
+        // the keys are not part of the game
+        let without_keys = |messages: Vec<Outgoing>| -> Vec<(usize, Msg)> {
+            events(messages)
+                .into_iter()
+                .filter(|(_, msg)| !matches!(msg, Msg::Room { .. }))
+                .collect()
+        };
+        let (room, messages) = start(7);
+        let (same_room, same_messages) = start(7);
+        let (other_room, other_messages) = start(8);
+
+        assert_eq!(without_keys(messages.clone()), without_keys(same_messages));
+        assert_ne!(without_keys(messages), without_keys(other_messages));
+        assert_ne!(room.player_keys, same_room.player_keys);
+        assert_ne!(room.room_key, same_room.room_key);
+
+        let record = room.record().unwrap();
+        assert!(record.seed.is_some());
+        assert_eq!(same_room.record().unwrap(), record);
+        assert_ne!(other_room.record().unwrap(), record);
+
+        // bots are recorded too
+        let mut room = Room::with_seed(33, "Akagi".to_owned(), Ruleset::default(), 7);
+        room.connect_bot(DEFAULT_STRATEGY).unwrap();
+        let record = room.record().unwrap();
+        assert_eq!(record.players[0].bot_seed, None);
+        assert!(record.players[1].bot_seed.is_some());
+    }
+
+    // Drops event numbers
+    fn events(messages: Vec<Outgoing>) -> Vec<(usize, Msg)> {
+        messages
+            .into_iter()
+            .map(|(user_id, _, msg)| (user_id, msg))
+            .collect()
+    }
+
     fn replayed(msg: &Msg) -> Option<&Msg> {
         match msg {
             Msg::Replay { msg } => Some(msg),
@@ -243,7 +760,7 @@ mod tests {
         room.disconnect(55);
 
         assert_eq!(room.user_ids[1], None);
-        let messages = room.rejoin(55, 1).unwrap();
+        let messages = events(room.rejoin(55, 1, None).unwrap());
         assert_eq!(room.user_ids[1], Some(55));
         assert_eq!(messages.len(), 4);
         assert!(matches!(messages[0], (55, Msg::Replay { .. })));
@@ -258,10 +775,58 @@ mod tests {
         assert!(matches!(replayed(&messages[3].1).unwrap(), Msg::StartMove {..}));
     }
 
+    #[test]
+    fn test_resume() {
+        let mut room = Room::new(33, "Akagi".to_owned());
+        room.connect(55, "Washizu".to_owned()).unwrap();
+        room.disconnect(55);
+
+        // Room, PhaseOne, StartMove
+        let messages = room.rejoin(55, 1, Some(2)).unwrap();
+        assert_eq!(messages.len(), 2);
+        match &messages[0] {
+            (55, Some(3), msg) => {
+                assert!(matches!(replayed(msg).unwrap(), Msg::StartMove { .. }))
+            }
+            _ => unreachable!(),
+        }
+        assert!(matches!(replayed(&messages[1].2).unwrap(), Msg::StartMove {..}));
+
+        // nothing missed
+        room.disconnect(55);
+        let messages = room.rejoin(55, 1, Some(3)).unwrap();
+        assert_eq!(messages.len(), 1);
+
+        // history is gone
+        room.disconnect(55);
+        room.trim_history();
+        let messages = room.rejoin(55, 1, Some(2)).unwrap();
+        assert_eq!(messages.len(), 3);
+        assert!(matches!(replayed(&messages[0].2).unwrap(), Msg::Room { you: 1, .. }));
+        assert!(matches!(
+            messages[1],
+            (55, Some(3), Msg::Snapshot { you: 1, phase_two: false, .. })
+        ));
+
+        // seen more than was sent
+        room.disconnect(55);
+        let messages = room.rejoin(55, 1, Some(4)).unwrap();
+        assert_eq!(messages.len(), 3);
+        assert!(matches!(messages[1], (55, Some(3), Msg::Snapshot { .. })));
+
+        // numbering goes on
+        let tiles = match messages[1].2.clone() {
+            Msg::Snapshot { tiles, .. } => tiles,
+            _ => unreachable!(),
+        };
+        let messages = room.on_message(55, Msg::Hand { hand: tiles[0..13].to_vec() }).unwrap();
+        assert!(matches!(messages[0], (55, Some(4), _)));
+    }
+
     #[test]
     fn test_on_message() {
         let mut room = Room::new(33, "Akagi".to_owned());
-        let messages = room.connect(55, "Washizu".to_owned()).unwrap();
+        let messages = events(room.connect(55, "Washizu".to_owned()).unwrap());
 
         let hand = match messages[2].1 {
             Msg::PhaseOne { ref tiles, .. } => tiles[0..13].to_vec(),
@@ -271,6 +836,7 @@ mod tests {
         let messages = room
             .on_message(33, Msg::Hand { hand: hand.clone() })
             .unwrap();
+        let messages = events(messages);
 
         assert_eq!(
             messages,
@@ -289,6 +855,7 @@ mod tests {
         let messages = room
             .on_message(33, Msg::Discard { tile: Tile::M1 })
             .unwrap();
+        let messages = events(messages);
         println!("{:?}", messages);
         assert_eq!(
             messages,
@@ -317,4 +884,268 @@ mod tests {
         assert_eq!(room.finished(), true);
         assert_eq!(room.describe(), None);
     }
+
+    #[test]
+    fn test_match() {
+        let rules = Ruleset {
+            hands: 2,
+            discards: 1,
+            ..Ruleset::default()
+        };
+        let mut room = Room::with_rules(33, "Akagi".to_owned(), rules.clone());
+        let messages = events(room.connect(55, "Washizu".to_owned()).unwrap());
+        let status = Msg::MatchStatus {
+            hand: 0,
+            hands: 2,
+            honba: 0,
+            points: [0, 0],
+        };
+        assert_eq!(messages[2], (33, status.clone()));
+        assert_eq!(messages[3], (55, status));
+
+        let mut tiles = vec![];
+        let mut east = 0;
+        for (user_id, msg) in messages.iter() {
+            if let Msg::PhaseOne {
+                tiles: t, east: e, ..
+            } = msg
+            {
+                tiles.push((*user_id, t.clone()));
+                east = *e;
+            }
+        }
+        for (user_id, t) in tiles.iter() {
+            room.on_message(*user_id, Msg::Hand { hand: t[0..13].to_vec() })
+                .unwrap();
+        }
+
+        // Play until either ron or draw
+        let mut messages = vec![];
+        for i in [east, 1 - east].iter() {
+            let (user_id, ref t) = tiles[*i];
+            messages = events(room.on_message(user_id, Msg::Discard { tile: t[13] }).unwrap());
+            if room.game.as_ref().unwrap().finished {
+                break;
+            }
+        }
+        assert!(messages
+            .iter()
+            .any(|(_, msg)| matches!(msg, Msg::MatchStatus { hand: 1, .. })));
+        assert_eq!(room.describe().is_some(), true);
+        assert!(room.on_message(33, Msg::Discard { tile: Tile::M1 }).is_err());
+
+        for _ in 0..rules.next_hand_delay {
+            assert_eq!(room.beat(), vec![]);
+        }
+        let messages = events(room.beat());
+        assert!(matches!(messages[0], (33, Msg::MatchStatus { hand: 1, .. })));
+        assert!(messages.iter().any(
+            |(_, msg)| matches!(msg, Msg::PhaseOne { east: e, .. } if *e == 1 - east)
+        ));
+    }
+
+    #[test]
+    fn test_rematch() {
+        let mut room = Room::new(33, "Akagi".to_owned());
+        room.connect(55, "Washizu".to_owned()).unwrap();
+        let keys = room.player_keys.clone();
+        assert!(room.on_message(33, Msg::RequestRematch).is_err());
+
+        // abort the game
+        room.on_message(33, Msg::Discard { tile: Tile::M1 }).unwrap();
+
+        let messages = events(room.on_message(55, Msg::RequestRematch).unwrap());
+        assert_eq!(
+            messages,
+            vec![
+                (33, Msg::RematchOffered { player: 1 }),
+                (55, Msg::RematchOffered { player: 1 }),
+            ]
+        );
+
+        let messages = events(room.on_message(33, Msg::RequestRematch).unwrap());
+        assert_eq!(messages.len(), 8);
+        assert!(matches!(messages[0], (33, Msg::RematchOffered { player: 0 })));
+        assert!(matches!(messages[1], (55, Msg::RematchOffered { player: 0 })));
+        assert_eq!(
+            messages[2],
+            (
+                55,
+                Msg::Room {
+                    you: 0,
+                    nicks: ["Washizu".to_owned(), "Akagi".to_owned()],
+                    key: keys[1].clone(),
+                }
+            )
+        );
+        assert_eq!(
+            messages[3],
+            (
+                33,
+                Msg::Room {
+                    you: 1,
+                    nicks: ["Washizu".to_owned(), "Akagi".to_owned()],
+                    key: keys[0].clone(),
+                }
+            )
+        );
+        assert!(matches!(messages[4], (55, Msg::PhaseOne { .. })));
+        assert_eq!(room.player_keys, [keys[1].clone(), keys[0].clone()]);
+        assert!(room.describe().is_some());
+
+        // the history only contains the new game
+        room.disconnect(33);
+        let messages = events(room.rejoin(33, 1, None).unwrap());
+        assert!(matches!(replayed(&messages[0].1).unwrap(), Msg::Room { you: 1, .. }));
+    }
+
+    #[test]
+    fn test_rematch_disconnect() {
+        let mut room = Room::new(33, "Akagi".to_owned());
+        room.connect(55, "Washizu".to_owned()).unwrap();
+        room.on_message(33, Msg::Discard { tile: Tile::M1 }).unwrap();
+
+        // the offer is withdrawn when leaving
+        room.on_message(55, Msg::RequestRematch).unwrap();
+        room.disconnect(55);
+        let messages = events(room.on_message(33, Msg::RequestRematch).unwrap());
+        assert_eq!(messages, vec![(33, Msg::RematchOffered { player: 0 })]);
+        assert!(room.game_finished());
+
+        // and has to be made again after rejoining
+        room.rejoin(55, 1, None).unwrap();
+        assert!(room.game_finished());
+        let messages = events(room.on_message(55, Msg::RequestRematch).unwrap());
+        assert!(messages
+            .iter()
+            .any(|(_, msg)| matches!(msg, Msg::PhaseOne { .. })));
+    }
+
+    #[test]
+    fn test_bot() {
+        let mut room = Room::new(33, "Akagi".to_owned());
+        let messages = events(room.connect_bot(DEFAULT_STRATEGY).unwrap());
+        assert_eq!(room.nicks[1], BOT_NICK);
+        assert!(messages.iter().all(|(user_id, _)| *user_id == 33));
+
+        let hand = match messages[1].1 {
+            Msg::PhaseOne { ref tiles, .. } => tiles[0..13].to_vec(),
+            _ => unreachable!("wrong message"),
+        };
+        room.on_message(33, Msg::Hand { hand }).unwrap();
+
+        // The bot moves on a beat
+        let messages = events(room.beat());
+        assert!(messages.contains(&(33, Msg::PhaseTwo)));
+
+        // The room, including the bot, survives saving
+        let json = serde_json::to_string(&room).unwrap();
+        let mut room: Room = serde_json::from_str(&json).unwrap();
+        room.user_ids[0] = Some(33);
+        assert!(room.bots[1].is_some());
+
+        // The bot agrees to a rematch
+        room.on_message(33, Msg::Hand { hand: vec![] }).unwrap();
+        assert!(room.game.as_ref().unwrap().finished);
+        let messages = events(room.on_message(33, Msg::RequestRematch).unwrap());
+        assert!(messages.contains(&(33, Msg::RematchOffered { player: 1 })));
+        assert!(room.bots[0].is_some());
+        assert!(messages
+            .iter()
+            .any(|(_, msg)| matches!(msg, Msg::PhaseOne { you: 1, .. })));
+    }
+
+    #[test]
+    fn test_bot_deferred() {
+        let mut room = Room::new(33, "Akagi".to_owned());
+        let messages = events(room.connect_bot(DEFAULT_STRATEGY).unwrap());
+        let hand = match messages[1].1 {
+            Msg::PhaseOne { ref tiles, .. } => tiles[0..13].to_vec(),
+            _ => unreachable!("wrong message"),
+        };
+        room.on_message(33, Msg::Hand { hand }).unwrap();
+
+        // No move until the task is done
+        let (messages, mut tasks) = room.beat_deferred();
+        assert!(messages.is_empty());
+        assert_eq!(tasks.len(), 1);
+        assert!(room.beat_deferred().1.is_empty());
+        let task = tasks.remove(0);
+
+        // A result from before a restore is ignored, and the move chosen again
+        let json = serde_json::to_string(&room).unwrap();
+        let mut restored: Room = serde_json::from_str(&json).unwrap();
+        restored.user_ids[0] = Some(33);
+        let (_, mut tasks) = restored.beat_deferred();
+        assert_eq!(tasks.len(), 1);
+        assert!(restored.bot_moved(task.run()).is_empty());
+        let messages = events(restored.bot_moved(tasks.remove(0).run()));
+        assert!(messages.contains(&(33, Msg::PhaseTwo)));
+    }
+
+    #[test]
+    fn test_spectate() {
+        let mut room = Room::new(33, "Akagi".to_owned());
+        assert!(room.spectate(77).is_err());
+        let messages = events(room.connect(55, "Washizu".to_owned()).unwrap());
+        let hand = match messages[2].1 {
+            Msg::PhaseOne { ref tiles, .. } => tiles[0..13].to_vec(),
+            _ => unreachable!("wrong message"),
+        };
+
+        let messages = events(room.spectate(77).unwrap());
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            replayed(&messages[0].1).unwrap(),
+            &Msg::Spectating {
+                nicks: ["Akagi".to_owned(), "Washizu".to_owned()],
+            }
+        );
+        assert!(matches!(
+            replayed(&messages[1].1).unwrap(),
+            Msg::SpectatePhaseOne { .. }
+        ));
+        assert!(room.spectate(77).is_err());
+        assert!(room.spectate(33).is_err());
+
+        // hands are not shown
+        let messages = events(room.on_message(33, Msg::Hand { hand }).unwrap());
+        assert!(messages.iter().all(|(user_id, _)| *user_id == 33));
+
+        let messages = room
+            .on_message(55, Msg::Discard { tile: Tile::M1 })
+            .unwrap();
+        let messages = events(messages);
+        assert!(messages.contains(&(
+            77,
+            Msg::Abort {
+                culprit: 1,
+                description: "discard too soon".to_owned(),
+            }
+        )));
+
+        room.stop_spectating(77);
+        let messages = events(room.on_message(33, Msg::RequestRematch).unwrap());
+        assert!(messages.iter().all(|(user_id, _)| *user_id != 77));
+    }
+
+    #[test]
+    fn test_private() {
+        let mut room = Room::new(33, "Akagi".to_owned());
+        room.set_private(Some(PrivateRoom {
+            password: Some("secret".to_owned()),
+            invited: vec!["Washizu".to_owned()],
+        }));
+        assert!(room.private());
+        let json = serde_json::to_string(&room).unwrap();
+        assert!(!json.contains("secret"));
+
+        assert!(room.check_access("Washizu", None).is_err());
+        assert!(room.check_access("Washizu", Some("guess")).is_err());
+        assert!(room.check_access("Ichikawa", Some("secret")).is_err());
+        assert!(room.check_access("Washizu", Some("secret")).is_ok());
+
+        room.connect(55, "Washizu".to_owned()).unwrap();
+        assert!(room.started());
+    }
 }
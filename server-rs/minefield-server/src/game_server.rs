@@ -11,14 +11,20 @@ use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio_tungstenite::tungstenite::Message;
 use tokio_tungstenite::WebSocketStream;
 
-use minefield_game::lobby::Lobby;
+use minefield_game::lobby::{self, Lobby};
 
 type Reader = SplitStream<WebSocketStream<Upgraded>>;
 type Writer = SplitSink<WebSocketStream<Upgraded>, Message>;
 
 pub struct GameServer {
     lobby: Arc<Mutex<Lobby>>,
-    senders: Arc<Mutex<HashMap<usize, UnboundedSender<String>>>>,
+    senders: Arc<Mutex<HashMap<usize, Connection>>>,
+}
+
+struct Connection {
+    sender: UnboundedSender<String>,
+    // number of the last message sent
+    seq: u64,
 }
 
 impl Clone for GameServer {
@@ -30,7 +36,7 @@ impl Clone for GameServer {
     }
 }
 
-use minefield_game::protocol::Msg;
+use minefield_game::protocol::{Envelope, ErrorCode, Msg, Outgoing};
 
 #[derive(Debug, Fail)]
 pub enum CommError {
@@ -39,8 +45,11 @@ pub enum CommError {
 }
 
 impl GameServer {
-    pub fn open(db_path: &str) -> Result<Self, Error> {
-        let lobby = Lobby::open(db_path)?;
+    pub fn open(db_path: &str, seed: Option<u64>) -> Result<Self, Error> {
+        let mut lobby = Lobby::open(db_path)?;
+        if let Some(seed) = seed {
+            lobby.set_seed(seed)?;
+        }
         Ok(GameServer {
             lobby: Arc::new(Mutex::new(lobby)),
             senders: Arc::new(Mutex::new(HashMap::new())),
@@ -68,7 +77,10 @@ impl GameServer {
         let user_id = self.lobby().connect();
 
         let (sender, receiver) = unbounded_channel();
-        self.senders.lock().unwrap().insert(user_id, sender);
+        self.senders
+            .lock()
+            .unwrap()
+            .insert(user_id, Connection { sender, seq: 0 });
         tokio::task::spawn(self.clone().write_messages(writer, receiver, user_id));
 
         info!("[{}] connect", user_id);
@@ -86,9 +98,36 @@ impl GameServer {
             match message {
                 Message::Text(text) => {
                     info!("[{}] recv {}", user_id, text);
-                    let msg = serde_json::from_str(&text)?;
-                    let messages = self.lobby().on_message(user_id, msg)?;
-                    self.send_messages(messages)?;
+                    let (id, msg) = match serde_json::from_str::<Envelope>(&text) {
+                        Ok(envelope) => (envelope.id, envelope.msg),
+                        Err(err) => {
+                            let reply = Msg::Error {
+                                code: ErrorCode::InvalidMessage,
+                                message: err.to_string(),
+                                request: None,
+                            };
+                            self.send_messages(vec![(user_id, None, reply)], None)?;
+                            // Well-formed JSON, but not a message we know
+                            // (maybe from a newer client): keep going.
+                            if err.is_data() {
+                                continue;
+                            }
+                            return Err(err.into());
+                        }
+                    };
+                    let reply_to = id.map(|id| (user_id, id));
+                    let result = self.lobby().on_message(user_id, msg.clone());
+                    match result {
+                        Ok(messages) => self.send_messages(messages, reply_to)?,
+                        Err(err) => {
+                            let reply = lobby::error_msg(&err, Some(msg));
+                            self.send_messages(vec![(user_id, None, reply)], reply_to)?;
+                            if lobby::is_protocol_violation(&err) {
+                                return Err(err);
+                            }
+                            info!("[{}] error: {}", user_id, err);
+                        }
+                    }
                 }
                 Message::Close(_) => {
                     break;
@@ -103,12 +142,26 @@ impl GameServer {
         Ok(())
     }
 
-    fn send_messages(&self, messages: Vec<(usize, Msg)>) -> Result<(), Error> {
-        for (user_id, msg) in messages.into_iter() {
-            let senders = self.senders.lock().unwrap();
-            if let Some(sender) = senders.get(&user_id) {
-                let text = serde_json::to_string(&msg)?;
-                sender.send(text)?;
+    // Messages to the user in `reply_to` are replies to their request, and
+    // get its id.
+    fn send_messages(
+        &self,
+        messages: Vec<Outgoing>,
+        reply_to: Option<(usize, u64)>,
+    ) -> Result<(), Error> {
+        for (user_id, event, msg) in messages.into_iter() {
+            let mut senders = self.senders.lock().unwrap();
+            if let Some(conn) = senders.get_mut(&user_id) {
+                conn.seq += 1;
+                let id = reply_to.filter(|(to, _)| *to == user_id).map(|(_, id)| id);
+                let envelope = Envelope {
+                    id,
+                    seq: Some(conn.seq),
+                    event,
+                    msg,
+                };
+                let text = serde_json::to_string(&envelope)?;
+                conn.sender.send(text)?;
             }
         }
         Ok(())
@@ -142,7 +195,19 @@ impl GameServer {
 
     fn beat(&self) -> Result<(), Error> {
         // info!("beat");
-        let messages = self.lobby().beat();
-        self.send_messages(messages)
+        let (messages, tasks) = self.lobby().beat_deferred();
+        self.send_messages(messages, None)?;
+        // Bots think without holding the lobby, and move when they're done.
+        for task in tasks.into_iter() {
+            let server = self.clone();
+            tokio::task::spawn_blocking(move || {
+                let result = task.run();
+                let messages = server.lobby().bot_moved(result);
+                if let Err(err) = server.send_messages(messages, None) {
+                    error!("bot move error: {:?}", err);
+                }
+            });
+        }
+        Ok(())
     }
 }
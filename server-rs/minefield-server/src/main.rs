@@ -44,6 +44,12 @@ async fn main() {
                 .takes_value(true)
                 .default_value("minefield.db"),
         )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .help("Seed for new rooms, for reproducing games"),
+        )
         .get_matches();
 
     let host = matches
@@ -60,6 +66,9 @@ async fn main() {
 
     let static_path = matches.value_of("static_path");
     let db_path = matches.value_of("db_path").unwrap();
+    let seed = matches
+        .value_of("seed")
+        .map(|seed| seed.parse().expect("error parsing seed"));
 
-    server::start_server(&addr, &static_path, &db_path).await;
+    server::start_server(&addr, &static_path, &db_path, seed).await;
 }
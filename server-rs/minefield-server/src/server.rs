@@ -26,8 +26,13 @@ impl Clone for ServerParams {
     }
 }
 
-pub async fn start_server(addr: &SocketAddr, static_path: &Option<&str>, db_path: &str) {
-    let game_server = GameServer::open(db_path).unwrap();
+pub async fn start_server(
+    addr: &SocketAddr,
+    static_path: &Option<&str>,
+    db_path: &str,
+    seed: Option<u64>,
+) {
+    let game_server = GameServer::open(db_path, seed).unwrap();
     game_server.start_beat();
 
     let params = ServerParams {